@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Result};
+use fancy_regex::Regex;
+use reqwest::{header::HeaderMap, Client};
+use serde::Deserialize;
+
+use crate::{
+    dynamic::{BiliDynamicResult, DynamicContent},
+    risk_control, wbi,
+};
+
+#[derive(Debug, Deserialize)]
+struct ArticleView {
+    data: ArticleViewData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArticleViewData {
+    content: Option<String>,
+    opus: Option<ArticleViewOpus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArticleViewOpus {
+    content: OpusContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusDetail {
+    data: OpusDetailData,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusDetailData {
+    item: OpusDetailItem,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusDetailItem {
+    modules: Vec<OpusModule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusModule {
+    module_content: Option<OpusContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusContent {
+    paragraphs: Vec<OpusParagraph>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusParagraph {
+    text: Option<OpusParagraphText>,
+    pic: Option<OpusParagraphPic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusParagraphText {
+    nodes: Vec<OpusTextNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusTextNode {
+    word: Option<OpusTextNodeWord>,
+    rich: Option<OpusTextNodeRich>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusTextNodeWord {
+    words: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusTextNodeRich {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusParagraphPic {
+    pics: Vec<OpusPic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpusPic {
+    url: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ArticleContent {
+    pub text: String,
+    pub images: Vec<String>,
+}
+
+impl OpusContent {
+    fn into_article_content(self) -> ArticleContent {
+        let mut content = ArticleContent::default();
+        for p in self.paragraphs {
+            if let Some(text) = p.text {
+                let line = text
+                    .nodes
+                    .into_iter()
+                    .filter_map(|x| x.word.map(|x| x.words).or(x.rich.map(|x| x.text)))
+                    .collect::<String>();
+                content.text.push_str(&line);
+                content.text.push('\n');
+            }
+            if let Some(pic) = p.pic {
+                content.images.extend(pic.pics.into_iter().map(|x| x.url));
+            }
+        }
+
+        content
+    }
+}
+
+pub async fn get_article(id: u64, client: &Client) -> Result<ArticleContent> {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        "Referer",
+        (format!("https://www.bilibili.com/read/cv{}", id)).parse()?,
+    );
+    let r: ArticleView = risk_control::get_json(
+        client,
+        &format!("https://api.bilibili.com/x/article/view?id={}", id),
+        headers,
+    )
+    .await?;
+
+    if let Some(opus) = r.data.opus {
+        return Ok(opus.content.into_article_content());
+    }
+
+    let html = r
+        .data
+        .content
+        .ok_or_else(|| anyhow!("Article cv{} has no content!", id))?;
+
+    let mut images = vec![];
+    for i in Regex::new(r#"<img[^>]*?(?:data-src|src)="([^"]+)""#)?.captures_iter(&html) {
+        if let Some(url) = i?.get(1) {
+            images.push(full_url(url.as_str()));
+        }
+    }
+
+    Ok(ArticleContent {
+        text: html2text::from_read(html.as_bytes(), 1000),
+        images,
+    })
+}
+
+pub async fn get_opus(dynamic_id: u64, client: &Client) -> Result<ArticleContent> {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        "Referer",
+        (format!("https://www.bilibili.com/opus/{}", dynamic_id)).parse()?,
+    );
+    let query = wbi::sign(
+        &[
+            ("id", dynamic_id.to_string()),
+            (
+                "features",
+                "onlyfansVote,onlyfansAssetsV2,decorationCard,htmlNewStyle".to_string(),
+            ),
+        ],
+        client,
+    )
+    .await?;
+    let r: OpusDetail = risk_control::get_json(
+        client,
+        &format!(
+            "https://api.bilibili.com/x/polymer/web-dynamic/v1/opus/detail?{}",
+            query
+        ),
+        headers,
+    )
+    .await?;
+
+    let mut content = ArticleContent::default();
+    for i in r
+        .data
+        .item
+        .modules
+        .into_iter()
+        .filter_map(|x| x.module_content)
+    {
+        let c = i.into_article_content();
+        content.text.push_str(&c.text);
+        content.images.extend(c.images);
+    }
+
+    Ok(content)
+}
+
+pub async fn expand(
+    dynamic: &mut BiliDynamicResult,
+    client: &Client,
+    excerpt_len: usize,
+) -> Result<()> {
+    if let DynamicContent::Forward {
+        origin: Some(origin),
+    } = &mut dynamic.content
+    {
+        return Box::pin(expand(origin, client, excerpt_len)).await;
+    }
+
+    let dynamic_id = dynamic.dynamic_id;
+    let (id, summary, covers) = match &mut dynamic.content {
+        DynamicContent::Article {
+            id,
+            summary,
+            covers,
+            ..
+        } => (*id, summary, covers),
+        _ => return Ok(()),
+    };
+
+    let content = if id != 0 {
+        get_article(id, client).await?
+    } else {
+        get_opus(dynamic_id, client).await?
+    };
+
+    let text = excerpt(&content.text, excerpt_len);
+    if !text.is_empty() {
+        *summary = Some(text);
+    }
+    for i in content.images {
+        if !covers.contains(&i) {
+            covers.push(i);
+        }
+    }
+
+    Ok(())
+}
+
+fn excerpt(s: &str, len: usize) -> String {
+    let s = s
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if s.chars().count() > len {
+        format!("{}……", s.chars().take(len).collect::<String>())
+    } else {
+        s
+    }
+}
+
+fn full_url(url: &str) -> String {
+    if url.starts_with("//") {
+        format!("https:{}", url)
+    } else {
+        url.to_string()
+    }
+}
+
+#[tokio::test]
+async fn test() {
+    let client = Client::new();
+    let article = get_article(1, &client).await.unwrap();
+    dbg!(article);
+}
+
+#[test]
+fn test_excerpt() {
+    assert_eq!(
+        excerpt("  小熊猫\n\n 今天也很可爱  \n", 10),
+        "小熊猫\n今天也很可爱"
+    );
+    assert_eq!(excerpt("小熊猫今天也很可爱", 3), "小熊猫……");
+}
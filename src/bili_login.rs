@@ -0,0 +1,319 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use fancy_regex::Regex;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use reqwest::{cookie::Jar, Client, Response, Url};
+use rsa::{pkcs8::DecodePublicKey, sha2::Sha256, Oaep, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use teloxide::Bot;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::sender;
+
+const QRCODE_GENERATE_URL: &str =
+    "https://passport.bilibili.com/x/passport-login/web/qrcode/generate";
+const QRCODE_POLL_URL: &str = "https://passport.bilibili.com/x/passport-login/web/qrcode/poll";
+const COOKIE_INFO_URL: &str = "https://passport.bilibili.com/x/passport-login/web/cookie/info";
+const COOKIE_REFRESH_URL: &str =
+    "https://passport.bilibili.com/x/passport-login/web/cookie/refresh";
+const CONFIRM_REFRESH_URL: &str =
+    "https://passport.bilibili.com/x/passport-login/web/confirm/refresh";
+const CORRESPOND_URL: &str = "https://www.bilibili.com/correspond/1";
+const COOKIE_URL: &str = "https://www.bilibili.com";
+const REDIS_KEY: &str = "bilibili-cookies";
+const PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDLgd2OAkcGVtoE3ThUREbio0Eg
+Uc/prcajMKXvkCKFCWhJYJcLkcM2DKKcSeFpD/j6Boy538YXnR6VhcuUJOhH2x71
+nzPjfdTcqMz7djHum0qSZA0AyCBDABUqCrfNgCiJ00Ra7GmRj+YCK1NJEuewlb40
+JNrRuoEUXpabUzGB8QIDAQAB
+-----END PUBLIC KEY-----";
+
+#[derive(Debug, Deserialize)]
+struct QrCodeGenerate {
+    data: QrCodeGenerateData,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodeGenerateData {
+    url: String,
+    qrcode_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodePoll {
+    data: QrCodePollData,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodePollData {
+    code: i64,
+    message: String,
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CookieInfo {
+    code: i64,
+    data: Option<CookieInfoData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CookieInfoData {
+    refresh: bool,
+    timestamp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CookieRefresh {
+    code: i64,
+    message: String,
+    data: Option<CookieRefreshData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CookieRefreshData {
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct BiliCookies {
+    sessdata: String,
+    bili_jct: String,
+    dede_user_id: String,
+    refresh_token: String,
+}
+
+impl BiliCookies {
+    fn update_from(&mut self, resp: &Response) {
+        for c in resp.cookies() {
+            match c.name() {
+                "SESSDATA" => self.sessdata = c.value().to_string(),
+                "bili_jct" => self.bili_jct = c.value().to_string(),
+                "DedeUserID" => self.dede_user_id = c.value().to_string(),
+                _ => (),
+            }
+        }
+    }
+
+    fn apply(&self, jar: &Arc<Jar>) -> Result<()> {
+        let url = Url::parse(COOKIE_URL)?;
+        for (name, value) in [
+            ("SESSDATA", &self.sessdata),
+            ("bili_jct", &self.bili_jct),
+            ("DedeUserID", &self.dede_user_id),
+        ] {
+            jar.add_cookie_str(
+                &format!("{}={}; Domain=.bilibili.com; Path=/", name, value),
+                &url,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn init(
+    con: &MultiplexedConnection,
+    client: &Client,
+    jar: &Arc<Jar>,
+    bot: Option<&Bot>,
+    admin_chat_id: Option<i64>,
+) -> Result<()> {
+    let mut con = con.clone();
+    let cookies: Option<String> = con.get(REDIS_KEY).await?;
+
+    if let Some(cookies) = cookies {
+        let cookies: BiliCookies = serde_json::from_str(&cookies)?;
+        cookies.apply(jar)?;
+        info!("Loaded bilibili cookies of user {}", cookies.dede_user_id);
+
+        return check_and_refresh(&con, client, jar, bot, admin_chat_id).await;
+    }
+
+    qrcode_login(&con, client, jar, bot, admin_chat_id).await
+}
+
+pub async fn check_and_refresh(
+    con: &MultiplexedConnection,
+    client: &Client,
+    jar: &Arc<Jar>,
+    bot: Option<&Bot>,
+    admin_chat_id: Option<i64>,
+) -> Result<()> {
+    let mut con = con.clone();
+    let cookies: String = con
+        .get::<_, Option<String>>(REDIS_KEY)
+        .await?
+        .ok_or_else(|| anyhow!("bilibili cookies is not found!"))?;
+    let mut cookies: BiliCookies = serde_json::from_str(&cookies)?;
+
+    let info = client
+        .get(COOKIE_INFO_URL)
+        .query(&[("csrf", &cookies.bili_jct)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<CookieInfo>()
+        .await?;
+
+    if info.code == -101 {
+        warn!("Bilibili session is expired, trying qrcode login ...");
+        return qrcode_login(&con, client, jar, bot, admin_chat_id).await;
+    }
+
+    let info = info
+        .data
+        .ok_or_else(|| anyhow!("Can not get bilibili cookie info!"))?;
+
+    if !info.refresh {
+        return Ok(());
+    }
+
+    info!("Refreshing bilibili cookies ...");
+    let refresh_csrf = get_refresh_csrf(client, info.timestamp).await?;
+    let old_refresh_token = cookies.refresh_token.clone();
+
+    let resp = client
+        .post(COOKIE_REFRESH_URL)
+        .form(&[
+            ("csrf", cookies.bili_jct.as_str()),
+            ("refresh_csrf", refresh_csrf.as_str()),
+            ("source", "main_web"),
+            ("refresh_token", old_refresh_token.as_str()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?;
+    cookies.update_from(&resp);
+    let json = resp.json::<CookieRefresh>().await?;
+
+    if json.code != 0 {
+        bail!("Failed to refresh bilibili cookies: {}", json.message);
+    }
+
+    cookies.refresh_token = json
+        .data
+        .ok_or_else(|| anyhow!("Can not get new refresh token!"))?
+        .refresh_token;
+    cookies.apply(jar)?;
+    con.set::<_, _, ()>(REDIS_KEY, serde_json::to_string(&cookies)?)
+        .await?;
+
+    client
+        .post(CONFIRM_REFRESH_URL)
+        .form(&[
+            ("csrf", cookies.bili_jct.as_str()),
+            ("refresh_token", old_refresh_token.as_str()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!("Bilibili cookies is refreshed");
+
+    Ok(())
+}
+
+async fn get_refresh_csrf(client: &Client, timestamp: i64) -> Result<String> {
+    let path = correspond_path(timestamp)?;
+    let text = client
+        .get(format!("{}/{}", CORRESPOND_URL, path))
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    let refresh_csrf = Regex::new(r#"(?<=<div id="1-name">)[^<]+"#)?
+        .find(&text)?
+        .ok_or_else(|| anyhow!("Can not get refresh csrf!"))?
+        .as_str()
+        .to_string();
+
+    Ok(refresh_csrf)
+}
+
+fn correspond_path(timestamp: i64) -> Result<String> {
+    let key = RsaPublicKey::from_public_key_pem(PUBLIC_KEY)?;
+    let encrypted = key.encrypt(
+        &mut rand::thread_rng(),
+        Oaep::new::<Sha256>(),
+        format!("refresh_{}", timestamp).as_bytes(),
+    )?;
+
+    Ok(encrypted.iter().map(|x| format!("{:02x}", x)).collect())
+}
+
+async fn qrcode_login(
+    con: &MultiplexedConnection,
+    client: &Client,
+    jar: &Arc<Jar>,
+    bot: Option<&Bot>,
+    admin_chat_id: Option<i64>,
+) -> Result<()> {
+    let mut con = con.clone();
+    let qr = client
+        .get(QRCODE_GENERATE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<QrCodeGenerate>()
+        .await?
+        .data;
+
+    sender::show_qrcode(
+        &qr.url,
+        "请使用 B 站客户端扫描二维码登录",
+        bot,
+        admin_chat_id,
+    )
+    .await?;
+
+    let started_at = Instant::now();
+
+    loop {
+        sleep(Duration::from_secs(3)).await;
+
+        let resp = client
+            .get(QRCODE_POLL_URL)
+            .query(&[("qrcode_key", &qr.qrcode_key)])
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut cookies = BiliCookies::default();
+        cookies.update_from(&resp);
+        let poll = resp.json::<QrCodePoll>().await?.data;
+
+        match poll.code {
+            0 => {
+                cookies.refresh_token = poll.refresh_token;
+                cookies.apply(jar)?;
+                con.set::<_, _, ()>(REDIS_KEY, serde_json::to_string(&cookies)?)
+                    .await?;
+                info!(
+                    "Bilibili login successfully! Hello {}",
+                    cookies.dede_user_id
+                );
+
+                return Ok(());
+            }
+            86101 | 86090 => {
+                if started_at.elapsed() > Duration::from_secs(180) {
+                    bail!("Bilibili qrcode login timeout!");
+                }
+            }
+            _ => bail!("Failed to login bilibili: {}", poll.message),
+        }
+    }
+}
+
+#[test]
+fn test_correspond_path() {
+    let path = correspond_path(1684468084078).unwrap();
+    assert_eq!(path.len(), 256);
+}
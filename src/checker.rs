@@ -1,89 +1,149 @@
+use std::{collections::HashMap, future::Future};
+
 use anyhow::{anyhow, Result};
 use redis::{aio::MultiplexedConnection, AsyncCommands};
 use reqwest::Client;
-use teloxide::{adaptors::AutoSend, Bot};
+use teloxide::Bot;
 use time::{format_description, macros::offset, OffsetDateTime};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
-    dynamic, live,
+    article, comment,
+    dynamic::DynamicAdditional,
+    dynamic::{self, BiliDynamicResult, DynamicContent},
+    live, outbox,
+    reservation::{self, LiveReservations, Reservation},
+    risk_control::BiliError,
     sender::{self, TelegramSend},
-    weibo::{self, WeiboClient},
+    snapshot::{self, Snapshot},
+    subscription::Route,
+    video,
+    weibo::{WeiboClient, WeiboError, WeiboIndexDataCardMblog},
 };
 
+const COMMENT_DYNAMIC_COUNT: usize = 5;
+const COMMENT_WEIBO_COUNT: usize = 5;
+
 pub async fn check_dynamic_update(
     con: &MultiplexedConnection,
     uid: u64,
     client: &Client,
-    bot: Option<&AutoSend<Bot>>,
-    telegram_chat_id: Option<i64>,
+    bot: Option<&Bot>,
+    chats: Vec<Route>,
+    article_excerpt: usize,
 ) -> Result<()> {
     let mut con = con.clone();
     info!("checking {} dynamic update ...", uid);
     let key = format!("dynamic-{}", uid);
     let key2 = format!("dynamic-{}-updated-id", uid);
     let dynamic = dynamic::get_ailurus_dynamic(uid, client).await?;
+    let latest_timestamp = dynamic
+        .iter()
+        .map(|x| x.timestamp)
+        .max()
+        .ok_or_else(|| anyhow!("{} dynamic is empty!", uid))?;
     let v: Result<u64> = con.get(&key).await.map_err(|e| anyhow!("{}", e));
     if v.is_err() {
         info!("Creating new spy {}...", &key);
-        con.set(
-            &key,
-            dynamic
-                .first()
-                .ok_or_else(|| anyhow!("{} dynamic is empty!", uid))?
-                .timestamp,
-        )
-        .await?;
+        con.set::<_, _, ()>(&key, latest_timestamp).await?;
     }
     let mut is_update = false;
-    let mut telegram_sends = vec![];
     if let Ok(t) = v {
+        // fetched before the snapshots are compared and anything is claimed,
+        // so risk control does not lose the dynamics or their edits
+        let mut votes = HashMap::new();
+        for i in dynamic.iter().filter(|x| x.timestamp > t) {
+            if let Some(DynamicAdditional::Vote { vote_id, .. }) = &i.additional {
+                match dynamic::get_vote_options(*vote_id, client).await {
+                    Ok(options) => {
+                        votes.insert(i.dynamic_id, options);
+                    }
+                    Err(e) if e.is::<BiliError>() => return Err(e),
+                    Err(e) => warn!("Can not get vote {} options: {}", vote_id, e),
+                }
+            }
+        }
+        let snapshot_key = format!("bilibili-{}-snapshots", uid);
+        let name = dynamic
+            .iter()
+            .find_map(|x| x.user.clone())
+            .unwrap_or_else(|| uid.to_string());
+        let current = dynamic.iter().map(dynamic_snapshot).collect::<Vec<_>>();
+        let prefix = format!("bili_dynamic:{}", uid);
+        let SnapshotChanges {
+            mut telegram_sends,
+            mut snapshots,
+            deleted,
+        } = check_snapshots(
+            &con,
+            &snapshot_key,
+            &current,
+            &name,
+            "动态",
+            &prefix,
+            |id| dynamic::is_deleted(id, client),
+        )
+        .await?;
         for i in &dynamic {
             if i.timestamp > t {
+                let snapshot = dynamic_snapshot(i);
+                let mut i = i.clone();
+                if let (Some(DynamicAdditional::Vote { options, .. }), Some(o)) =
+                    (&mut i.additional, votes.remove(&i.dynamic_id))
+                {
+                    *options = o;
+                }
+                if article_excerpt > 0 {
+                    if let Err(e) = article::expand(&mut i, client, article_excerpt).await {
+                        warn!("Can not get article of dynamic {}: {}", i.dynamic_id, e);
+                    }
+                }
                 is_update = true;
+                if let DynamicContent::Video {
+                    bvid: Some(bvid), ..
+                } = &i.content
+                {
+                    if !claim_video(&mut con, uid, bvid).await? {
+                        info!("Video {} is already notified, skip dynamic", bvid);
+                        continue;
+                    }
+                }
+                if let Some(DynamicAdditional::LiveReservation { rid, .. }) = &i.additional {
+                    claim_reservation(&mut con, uid, *rid).await?;
+                }
                 let name = if let Some(name) = i.user.clone() {
                     name
                 } else {
-                    format!("{}", uid)
+                    format!("{}", i.uid.unwrap_or(uid))
                 };
-                let desc = if let Some(desc) = i.description.clone() {
-                    desc
-                } else {
-                    "None".to_string()
-                };
-                info!("用户「{}」有新动态！内容：{}", name, desc);
-                let date = timestamp_to_date(i.timestamp)?;
-                let url = format!("https://t.bilibili.com/{}", i.dynamic_id);
-                let s = format!(
-                    "<b>「{}」有新动态啦！</b>\n{}\n{}\n\n{}",
-                    name,
-                    date,
-                    desc.replace('<', "【").replace('>', "】"),
-                    url
-                );
-                let group = if let Some(picture) = i.picture.clone() {
-                    let mut group = Vec::new();
-                    for i in picture {
-                        if let Some(img) = i.img_src {
-                            group.push(img);
-                        }
-                    }
-                    Some(group)
-                } else {
-                    None
-                };
-                telegram_sends.push(TelegramSend {
-                    msg: s,
-                    photos: group,
-                    photo: None,
-                });
-                con.set(&key2, i.dynamic_id).await?;
+                let body = i.render();
+                info!("用户「{}」{}！内容：{}", name, i.action(), i.plain_text());
+                let s = bili_message(&name, i.action(), i.timestamp, &body, &i.url())?;
+                let pictures = i.pictures();
+                telegram_sends.push((
+                    format!("{}:{}:new", prefix, i.dynamic_id),
+                    TelegramSend {
+                        msg: s,
+                        photos: if pictures.is_empty() {
+                            None
+                        } else {
+                            Some(pictures)
+                        },
+                        ..Default::default()
+                    },
+                ));
+                snapshots.push(Some(snapshot));
+                con.set::<_, _, ()>(&key2, i.dynamic_id).await?;
             }
         }
-        check_and_send(bot, telegram_chat_id, telegram_sends, client).await?;
+        check_and_send_snapshots(&con, bot, &chats, telegram_sends, &snapshot_key, snapshots)
+            .await?;
+        for id in deleted {
+            snapshot::remove(&con, &snapshot_key, id).await?;
+        }
         if is_update {
             info!("Update {} timestamp", key);
-            con.set(&key, dynamic[0].timestamp).await?;
+            con.set::<_, _, ()>(&key, latest_timestamp).await?;
         }
     } else {
         error!("{}", v.unwrap_err());
@@ -92,24 +152,339 @@ pub async fn check_dynamic_update(
     Ok(())
 }
 
+pub async fn check_video_update(
+    con: &MultiplexedConnection,
+    uid: u64,
+    client: &Client,
+    bot: Option<&Bot>,
+    chats: Vec<Route>,
+) -> Result<()> {
+    let mut con = con.clone();
+    info!("checking {} video update ...", uid);
+    let key = format!("video-{}", uid);
+    let videos = video::get_ailurus_videos(uid, client).await?;
+    let latest_created = videos
+        .iter()
+        .map(|x| x.created)
+        .max()
+        .ok_or_else(|| anyhow!("{} video is empty!", uid))?;
+    let v: Result<u64> = con.get(&key).await.map_err(|e| anyhow!("{}", e));
+    let t = match v {
+        Ok(t) => t,
+        Err(_) => {
+            info!("Creating new spy {}...", &key);
+            for i in &videos {
+                claim_video(&mut con, uid, &i.bvid).await?;
+            }
+            con.set::<_, _, ()>(&key, latest_created).await?;
+            return Ok(());
+        }
+    };
+
+    let mut telegram_sends = vec![];
+    for i in videos.iter().filter(|x| x.created > t) {
+        if !claim_video(&mut con, uid, &i.bvid).await? {
+            info!("Video {} is already notified by dynamic, skip", i.bvid);
+            continue;
+        }
+        let mut body = format!("<b>{}</b> ({})", sender::escape_html(&i.title), i.length);
+        if !i.description.is_empty() {
+            body.push_str(&format!("\n{}", sender::escape_html(&i.description)));
+        }
+        info!("用户「{}」投稿了视频！{}", i.author, i.title);
+        telegram_sends.push((
+            format!("bili_video:{}:{}", uid, i.bvid),
+            TelegramSend {
+                msg: bili_message(&i.author, "投稿了视频", i.created, &body, &i.url())?,
+                photo: Some(i.pic.clone()),
+                ..Default::default()
+            },
+        ));
+    }
+    check_and_send(&con, bot, &chats, telegram_sends).await?;
+    if latest_created > t {
+        info!("Update {} timestamp", key);
+        con.set::<_, _, ()>(&key, latest_created).await?;
+    }
+
+    Ok(())
+}
+
+async fn claim_video(con: &mut MultiplexedConnection, uid: u64, bvid: &str) -> Result<bool> {
+    let added: bool = con
+        .sadd(format!("bilibili-{}-notified-videos", uid), bvid)
+        .await?;
+
+    Ok(added)
+}
+
+pub async fn check_comment(
+    con: &MultiplexedConnection,
+    uid: u64,
+    client: &Client,
+    bot: Option<&Bot>,
+    chats: Vec<Route>,
+) -> Result<()> {
+    let mut con = con.clone();
+    info!("checking {} comment ...", uid);
+    let key = format!("comment-{}", uid);
+    let key_replies = format!("bilibili-{}-notified-replies", uid);
+    let is_new_spy: bool = !con.exists(&key).await?;
+    let dynamic = dynamic::get_ailurus_dynamic(uid, client).await?;
+
+    let mut telegram_sends = vec![];
+    let mut claimed = vec![];
+    let mut risk_control = None;
+    for d in dynamic.iter().take(COMMENT_DYNAMIC_COUNT) {
+        let area = match d.comment_area {
+            Some(area) => area,
+            None => continue,
+        };
+        // a closed comment area fails, the other dynamics are still checked
+        let replies = match comment::get_watched_replies(uid, area, client).await {
+            Ok(replies) => replies,
+            Err(e) => {
+                warn!("Can not get comments of dynamic {}: {}", d.dynamic_id, e);
+                if e.is::<BiliError>() {
+                    risk_control.get_or_insert(e);
+                }
+                continue;
+            }
+        };
+        for r in replies {
+            let notified: bool = con.sismember(&key_replies, r.rpid).await?;
+            if notified {
+                continue;
+            }
+            claimed.push(r.rpid);
+            if is_new_spy {
+                continue;
+            }
+            let owner = d.user.clone().unwrap_or_else(|| uid.to_string());
+            let (name, action, mut body) = if r.pinned {
+                (
+                    owner,
+                    "置顶了评论",
+                    format!(
+                        "{}: {}",
+                        sender::escape_html(&r.uname),
+                        sender::escape_html(&r.message)
+                    ),
+                )
+            } else if r.root.is_some() {
+                (
+                    r.uname.clone(),
+                    "回复了评论",
+                    sender::escape_html(&r.message),
+                )
+            } else {
+                (
+                    r.uname.clone(),
+                    "发表了评论",
+                    sender::escape_html(&r.message),
+                )
+            };
+            if let Some((root_uname, root_message)) = &r.root {
+                body.push_str(&format!(
+                    "\n\n// {}: {}",
+                    sender::escape_html(root_uname),
+                    sender::escape_html(root_message)
+                ));
+            }
+            info!("用户「{}」{}：{}", name, action, r.message);
+            telegram_sends.push((
+                format!("bili_comment:{}:{}", uid, r.rpid),
+                TelegramSend {
+                    msg: bili_message(
+                        &name,
+                        action,
+                        r.ctime,
+                        &body,
+                        &format!("{}#reply{}", d.url(), r.rpid),
+                    )?,
+                    ..Default::default()
+                },
+            ));
+        }
+    }
+
+    check_and_send(&con, bot, &chats, telegram_sends).await?;
+    // claimed once pushed, a failed push is retried in the next check
+    if !claimed.is_empty() {
+        con.sadd::<_, _, ()>(&key_replies, claimed).await?;
+    }
+    if is_new_spy {
+        info!("Creating new spy {}...", &key);
+        con.set::<_, _, ()>(&key, true).await?;
+    }
+
+    match risk_control {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+pub async fn check_reservation(
+    con: &MultiplexedConnection,
+    uid: u64,
+    client: &Client,
+    bot: Option<&Bot>,
+    chats: Vec<Route>,
+    remind_minutes: u64,
+) -> Result<()> {
+    let mut con = con.clone();
+    info!("checking {} live reservation ...", uid);
+    let key = format!("reservation-{}", uid);
+    let LiveReservations {
+        active: reservations,
+        cancelled,
+    } = reservation::get_live_reservations(uid, client).await?;
+    let v: Option<String> = con.get(&key).await?;
+
+    let old = match v {
+        Some(v) => serde_json::from_str::<Vec<Reservation>>(&v)?,
+        None => {
+            info!("Creating new spy {}...", &key);
+            for i in &reservations {
+                claim_reservation(&mut con, uid, i.sid).await?;
+            }
+            con.set::<_, _, ()>(&key, serde_json::to_string(&reservations)?)
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let name = reservation::get_user_name(uid, client).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp().try_into()?;
+    let mut telegram_sends = vec![];
+    let mut snapshot = vec![];
+
+    for mut i in reservations {
+        match old.iter().find(|x| x.sid == i.sid) {
+            Some(o) => {
+                i.reminded = o.reminded && o.start_time == i.start_time;
+                i.revision = o.revision;
+                if o.title != i.title || o.start_time != i.start_time {
+                    i.revision += 1;
+                    info!("用户「{}」修改了直播预约：{}", name, i.title);
+                    telegram_sends.push(reservation_send(&name, "修改了直播预约", &i, "edit")?);
+                }
+            }
+            None => {
+                if claim_reservation(&mut con, uid, i.sid).await? {
+                    info!("用户「{}」发布了直播预约：{}", name, i.title);
+                    telegram_sends.push(reservation_send(&name, "发布了直播预约", &i, "new")?);
+                }
+            }
+        }
+
+        if !i.reminded && now < i.start_time && now + remind_minutes * 60 >= i.start_time {
+            info!("用户「{}」的直播即将开始：{}", name, i.title);
+            telegram_sends.push(reservation_send(&name, "的直播即将开始", &i, "remind")?);
+            i.reminded = true;
+        }
+
+        snapshot.push(i);
+    }
+
+    let mut is_live = None;
+    for o in &old {
+        if o.start_time <= now || snapshot.iter().any(|x| x.sid == o.sid) {
+            continue;
+        }
+        // the reservation is also gone when the live starts early
+        if !cancelled.contains(&o.sid) {
+            if is_live.is_none() {
+                is_live = Some(live::is_user_live(uid, client).await?);
+            }
+            if is_live == Some(true) {
+                info!("用户「{}」的直播提前开始了：{}", name, o.title);
+                continue;
+            }
+        }
+        info!("用户「{}」取消了直播预约：{}", name, o.title);
+        telegram_sends.push(reservation_send(&name, "取消了直播预约", o, "cancel")?);
+    }
+
+    check_and_send(&con, bot, &chats, telegram_sends).await?;
+    con.set::<_, _, ()>(&key, serde_json::to_string(&snapshot)?)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_reservations(con: &MultiplexedConnection, uid: u64) -> Result<Vec<Reservation>> {
+    let mut con = con.clone();
+    let v: Option<String> = con.get(format!("reservation-{}", uid)).await?;
+
+    Ok(v.map(|x| serde_json::from_str(&x))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+async fn claim_reservation(con: &mut MultiplexedConnection, uid: u64, sid: u64) -> Result<bool> {
+    let added: bool = con
+        .sadd(format!("bilibili-{}-notified-reservations", uid), sid)
+        .await?;
+
+    Ok(added)
+}
+
+// an edit or a reminder after an edit is a new event, so the revision is in the id
+fn reservation_send(
+    name: &str,
+    action: &str,
+    reservation: &Reservation,
+    event: &str,
+) -> Result<(String, TelegramSend)> {
+    let id = format!(
+        "bili_reservation:{}:{}:{}:{}",
+        reservation.uid, reservation.sid, event, reservation.revision
+    );
+
+    Ok((
+        id,
+        TelegramSend {
+            msg: bili_message(
+                name,
+                action,
+                reservation.start_time,
+                &format!("<b>{}</b>", sender::escape_html(&reservation.title)),
+                &reservation.url(),
+            )?,
+            ..Default::default()
+        },
+    ))
+}
+
+fn bili_message(name: &str, action: &str, timestamp: u64, body: &str, url: &str) -> Result<String> {
+    Ok(format!(
+        "<b>「{}」{}！</b>\n{}\n{}\n\n{}",
+        sender::escape_html(name),
+        action,
+        timestamp_to_date(timestamp)?,
+        body,
+        url
+    ))
+}
+
 pub async fn check_live_status(
     con: &MultiplexedConnection,
     room_id: u64,
     client: &Client,
-    bot: Option<&AutoSend<Bot>>,
-    telegram_chat_id: Option<i64>,
+    bot: Option<&Bot>,
+    chats: Vec<Route>,
 ) -> Result<()> {
     let mut con = con.clone();
     info!("checking room {} live status update ...", room_id);
     let key = format!("live-{}-status", room_id);
+    // when the live started, the message ids of its notification are kept by it
+    let key_started_at = format!("live-{}-started-at", room_id);
     let live = live::get_live_status(room_id, client).await?;
     let db_live_status: Result<bool> = con.get(&key).await.map_err(|e| anyhow!(e));
     let ls = live.live_status;
-    let date = live.live_time;
-    if db_live_status.is_err() {
-        con.set(&key, ls == 1).await?;
-    } else {
-        let db_live_status = db_live_status.unwrap();
+    let date = live.live_time.clone();
+    if let Ok(db_live_status) = db_live_status {
         if !db_live_status && ls == 1 {
             let s = format!(
                 "<b>「{}」开播啦！</b>\n{}\n{}\n\n{}",
@@ -119,36 +494,126 @@ pub async fn check_live_status(
                 format_args!("https://live.bilibili.com/{}", live.room_id)
             );
             info!("{}", s);
-            let telegram_sends = vec![TelegramSend {
+            let started_at = live
+                .live_timestamp()
+                .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp());
+            con.set::<_, _, ()>(&key_started_at, started_at).await?;
+            let key_messages = live_messages_key(room_id, started_at);
+            let telegram_send = TelegramSend {
                 msg: s,
-                photos: None,
                 photo: Some(live.user_cover),
-            }];
-            check_and_send(bot, telegram_chat_id, telegram_sends, client).await?;
-            con.set(key, true).await?;
+                ..Default::default()
+            };
+            if bot.is_some() && !chats.is_empty() {
+                let id = format!("live:{}:start:{}", room_id, started_at);
+                outbox::push(&con, id, telegram_send, &chats, None, Some(key_messages)).await?;
+            }
+            con.set::<_, _, ()>(key, true).await?;
         } else if db_live_status && ls == 1 {
-            con.set(key, true).await?;
+            con.set::<_, _, ()>(key, true).await?;
         } else if ls != 1 {
-            con.set(key, false).await?;
+            if db_live_status {
+                end_live(&mut con, &live, &key_started_at, bot, &chats).await?;
+            }
+            con.set::<_, _, ()>(key, false).await?;
         }
+    } else {
+        con.set::<_, _, ()>(&key, ls == 1).await?;
     }
 
     Ok(())
 }
 
+// each live has its own key, a late delivered notification does not mix into the next live
+fn live_messages_key(room_id: u64, started_at: i64) -> String {
+    format!("live-{}-{}-messages", room_id, started_at)
+}
+
+// edits the live notification to the ended one, replies to it if can not edit
+async fn end_live(
+    con: &mut MultiplexedConnection,
+    live: &live::LiveStatusResult,
+    key_started_at: &str,
+    bot: Option<&Bot>,
+    chats: &[Route],
+) -> Result<()> {
+    let started_at: Option<i64> = con.get(key_started_at).await?;
+    let started_at = match started_at {
+        Some(started_at) => started_at,
+        // started before the sessions are recorded
+        None => return Ok(()),
+    };
+    // the live is over, a start notification not delivered yet is only sent as the ended one
+    let id = format!("live:{}:start:{}", live.room_id, started_at);
+    outbox::cancel(con, &id).await?;
+    let key_messages = live_messages_key(live.room_id, started_at);
+    let message_ids: HashMap<String, i32> = con.hgetall(&key_messages).await?;
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let s = format!(
+        "<b>「{}」已下播 (时长 {})</b>\n{}\n{}\n\n{}",
+        live.uname.replace('<', "【").replace('>', "】"),
+        format_duration(now - started_at),
+        timestamp_to_date(started_at.try_into()?)?,
+        live.title.replace('<', "【").replace('>', "】"),
+        format_args!("https://live.bilibili.com/{}", live.room_id)
+    );
+    info!("{}", s);
+
+    let bot = match bot {
+        Some(bot) => bot,
+        None => {
+            con.del::<_, ()>(&[key_started_at, &key_messages]).await?;
+            return Ok(());
+        }
+    };
+
+    let mut not_edited = vec![];
+    for route in chats {
+        match message_ids.get(&route.target()) {
+            Some(message_id) => match sender::edit_message(bot, route, *message_id, &s).await {
+                Ok(()) => continue,
+                Err(e) => warn!("Can not edit live message in {}: {}", route.target(), e),
+            },
+            None => warn!("Live message in {} is not found", route.target()),
+        }
+        not_edited.push(route.clone());
+    }
+
+    if !not_edited.is_empty() {
+        let telegram_send = TelegramSend {
+            msg: s,
+            reply_to: message_ids,
+            ..Default::default()
+        };
+        let id = format!("live:{}:end:{}", live.room_id, started_at);
+        outbox::push(con, id, telegram_send, &not_edited, None, None).await?;
+    }
+    // cleared once every chat is edited or replied, otherwise the next check tries again
+    con.del::<_, ()>(&[key_started_at, &key_messages]).await?;
+
+    Ok(())
+}
+
+fn format_duration(secs: i64) -> String {
+    let minutes = secs.max(0) / 60;
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h{}m", minutes / 60, minutes % 60)
+    }
+}
+
 pub async fn check_weibo(
     con: &MultiplexedConnection,
-    bot: Option<&AutoSend<Bot>>,
+    bot: Option<&Bot>,
     weibo: &WeiboClient,
-    profile_url: String,
-    client: &Client,
-    telegram_chat_id: Option<i64>,
+    uid: String,
+    chats: Vec<Route>,
 ) -> Result<()> {
-    info!("Checking {} weibo ...", profile_url);
+    info!("Checking {} weibo ...", uid);
     let mut con = con.clone();
 
-    let uid = weibo::get_uid(&profile_url)?;
-
     let key = format!("weibo-{}", uid);
     let key_container_id = format!("weibo-{}-containerid", uid);
     let v: Result<String> = con.get(&key).await.map_err(|e| anyhow!("{}", e));
@@ -157,8 +622,8 @@ pub async fn check_weibo(
         .await
         .map_err(|e| anyhow!("{}", e));
 
-    let (ailurus, container_id) = weibo.get_ailurus(&profile_url, containerid.ok()).await?;
-    con.set(&key_container_id, container_id).await?;
+    let (ailurus, container_id) = weibo.get_ailurus(&uid, containerid.ok()).await?;
+    con.set::<_, _, ()>(&key_container_id, container_id).await?;
 
     let data = ailurus
         .data
@@ -176,7 +641,8 @@ pub async fn check_weibo(
         .ok_or_else(|| anyhow!("Can not get mblog!"))?;
 
     if v.is_err() {
-        con.set(&key, first_mblog.created_at.clone()).await?;
+        con.set::<_, _, ()>(&key, first_mblog.created_at.clone())
+            .await?;
     }
 
     if let Ok(v) = v {
@@ -185,67 +651,387 @@ pub async fn check_weibo(
             .position(|x| x.mblog.as_ref().map(|x| &x.created_at) == Some(&v));
 
         if old_created_at_index.is_none() {
-            con.set(&key, first_mblog.created_at.clone()).await?;
+            con.set::<_, _, ()>(&key, first_mblog.created_at.clone())
+                .await?;
         }
 
         let old_created_at_index = old_created_at_index.unwrap_or(0);
 
-        let mut telegram_sends = vec![];
+        let snapshot_key = format!("weibo-{}-snapshots", uid);
+        let mut current = vec![];
+        for mblog in data.iter().filter_map(|x| x.mblog.as_ref()) {
+            current.push(weibo_snapshot(mblog, &uid)?);
+        }
+        let SnapshotChanges {
+            mut telegram_sends,
+            mut snapshots,
+            deleted,
+        } = check_snapshots(
+            &con,
+            &snapshot_key,
+            &current,
+            first_mblog.user_name(),
+            "微博",
+            &format!("weibo:{}", uid),
+            |id| async move { weibo.is_deleted(&id.to_string()).await },
+        )
+        .await?;
+
         for (i, c) in data.iter().enumerate() {
             if i < old_created_at_index {
                 let mblog = c
                     .mblog
                     .as_ref()
                     .ok_or_else(|| anyhow!("Can not get mblog!"))?;
-                let username = mblog.user.screen_name.clone();
-                let s = format!(
-                    "<b>「{}」发新微博啦！</b>\n{}\n{}\n{}",
-                    username,
-                    mblog.created_at,
-                    html2text::from_read(mblog.text.as_bytes(), 1000)
-                        .replace('<', "【")
-                        .replace('>', "】"),
-                    format_args!("https://weibo.com/{}/{}", uid, mblog.id)
-                );
-
-                info!("{}", s);
-
-                let photos = mblog
-                    .pics
-                    .as_ref()
-                    .map(|x| x.iter().map(|x| x.url.clone()).collect::<Vec<_>>());
-
-                telegram_sends.push(TelegramSend {
-                    msg: s,
-                    photos,
-                    photo: None,
-                });
+                let snapshot = weibo_snapshot(mblog, &uid)?;
+                if !claim_weibo(&mut con, &uid, &mblog.id).await? {
+                    continue;
+                }
+                telegram_sends.push(weibo_send(weibo, mblog, &uid, "发新微博啦").await);
+                snapshots.push(Some(snapshot));
             }
         }
 
-        check_and_send(bot, telegram_chat_id, telegram_sends, client).await?;
-        con.set(&key, first_mblog.created_at.clone()).await?;
+        check_and_send_snapshots(&con, bot, &chats, telegram_sends, &snapshot_key, snapshots)
+            .await?;
+        for id in deleted {
+            snapshot::remove(&con, &snapshot_key, id).await?;
+        }
+        con.set::<_, _, ()>(&key, first_mblog.created_at.clone())
+            .await?;
     }
 
     Ok(())
 }
 
+pub async fn check_weibo_topic(
+    con: &MultiplexedConnection,
+    bot: Option<&Bot>,
+    weibo: &WeiboClient,
+    uid: String,
+    topic: String,
+    chats: Vec<Route>,
+) -> Result<()> {
+    info!("Checking {} weibo super topic {} ...", uid, topic);
+    let mut con = con.clone();
+    let key = format!("weibo-topic-{}", uid);
+    let is_new_spy: bool = !con.exists(&key).await?;
+
+    let mut telegram_sends = vec![];
+    for mblog in weibo.get_topic_posts(&topic, &uid).await? {
+        if !claim_weibo(&mut con, &uid, &mblog.id).await? || is_new_spy {
+            continue;
+        }
+        telegram_sends.push(weibo_send(weibo, &mblog, &uid, "在超话发帖啦").await);
+    }
+
+    if is_new_spy {
+        info!("Creating new spy {}...", &key);
+        con.set::<_, _, ()>(&key, true).await?;
+    }
+    check_and_send(&con, bot, &chats, telegram_sends).await?;
+
+    Ok(())
+}
+
+pub async fn check_weibo_comment(
+    con: &MultiplexedConnection,
+    bot: Option<&Bot>,
+    weibo: &WeiboClient,
+    uid: String,
+    chats: Vec<Route>,
+) -> Result<()> {
+    info!("Checking {} weibo comment ...", uid);
+    let mut con = con.clone();
+    let key = format!("weibo-comment-{}", uid);
+    let key_comments = format!("weibo-{}-notified-comments", uid);
+    let is_new_spy: bool = !con.exists(&key).await?;
+    let containerid: Option<String> = con.get(format!("weibo-{}-containerid", uid)).await?;
+    let (ailurus, _) = weibo.get_ailurus(&uid, containerid).await?;
+
+    let mut telegram_sends = vec![];
+    let mut claimed = vec![];
+    let mut expired = None;
+    for mblog in ailurus
+        .data
+        .cards
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|x| x.card_type == Some(9))
+        .filter_map(|x| x.mblog)
+        .take(COMMENT_WEIBO_COUNT)
+    {
+        // a post with closed comments fails, the other posts are still checked
+        let comments = match weibo.get_watched_comments(&uid, &mblog.id).await {
+            Ok(comments) => comments,
+            Err(e) => {
+                warn!("Can not get comments of weibo {}: {}", mblog.id, e);
+                if e.is::<WeiboError>() {
+                    expired.get_or_insert(e);
+                }
+                continue;
+            }
+        };
+        for c in comments {
+            let notified: bool = con.sismember(&key_comments, &c.id).await?;
+            if notified {
+                continue;
+            }
+            claimed.push(c.id.clone());
+            if is_new_spy {
+                continue;
+            }
+            let action = if c.root.is_some() {
+                "回复了评论"
+            } else {
+                "发表了评论"
+            };
+            let mut body = sender::escape_html(&c.text);
+            if let Some((root_uname, root_text)) = &c.root {
+                body.push_str(&format!(
+                    "\n\n// {}: {}",
+                    sender::escape_html(root_uname),
+                    sender::escape_html(root_text)
+                ));
+            }
+            info!("用户「{}」{}：{}", c.uname, action, c.text);
+            telegram_sends.push((
+                format!("weibo_comment:{}:{}", uid, c.id),
+                TelegramSend {
+                    msg: weibo_message(
+                        &c.uname,
+                        action,
+                        &c.created_at,
+                        &body,
+                        &format!("https://weibo.com/{}/{}", uid, mblog.id),
+                    ),
+                    ..Default::default()
+                },
+            ));
+        }
+    }
+
+    check_and_send(&con, bot, &chats, telegram_sends).await?;
+    // claimed once pushed, a failed push is retried in the next check
+    if !claimed.is_empty() {
+        con.sadd::<_, _, ()>(&key_comments, claimed).await?;
+    }
+    if is_new_spy {
+        info!("Creating new spy {}...", &key);
+        con.set::<_, _, ()>(&key, true).await?;
+    }
+
+    match expired {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+async fn claim_weibo(con: &mut MultiplexedConnection, uid: &str, id: &str) -> Result<bool> {
+    let added: bool = con
+        .sadd(format!("weibo-{}-notified-posts", uid), id)
+        .await?;
+
+    Ok(added)
+}
+
+// a post in the super topic is also claimed as a post, so they share the id
+async fn weibo_send(
+    weibo: &WeiboClient,
+    mblog: &WeiboIndexDataCardMblog,
+    uid: &str,
+    action: &str,
+) -> (String, TelegramSend) {
+    let mut mblog = mblog.clone();
+    if let Err(e) = weibo.expand(&mut mblog).await {
+        warn!("Can not expand weibo {}: {}", mblog.id, e);
+    }
+    let s = weibo_message(
+        mblog.user_name(),
+        action,
+        &mblog.created_at,
+        &mblog.render(),
+        &format!("https://weibo.com/{}/{}", uid, mblog.id),
+    );
+
+    info!("{}", s);
+
+    let photos = Some(mblog.pictures()).filter(|x| !x.is_empty());
+    let animations = Some(mblog.live_photos()).filter(|x| !x.is_empty());
+
+    // the cover is sent instead when the video can not be uploaded
+    let (video, cover) = match mblog.video() {
+        Some((video, cover)) => (Some(video), cover),
+        None => (None, None),
+    };
+
+    (
+        format!("weibo:{}:{}:new", uid, mblog.id),
+        TelegramSend {
+            msg: s,
+            photos,
+            photo: cover,
+            video,
+            animations,
+            ..Default::default()
+        },
+    )
+}
+
+fn weibo_message(name: &str, action: &str, created_at: &str, body: &str, url: &str) -> String {
+    format!(
+        "<b>「{}」{}！</b>\n{}\n{}\n{}",
+        sender::escape_html(name),
+        action,
+        created_at,
+        body,
+        url
+    )
+}
+
+// the snapshots are committed only after the notifications are pushed,
+// a failed check finds the same changes again
+#[derive(Default)]
+struct SnapshotChanges {
+    telegram_sends: Vec<(String, TelegramSend)>,
+    // saved once delivered
+    snapshots: Vec<Option<Snapshot>>,
+    // removed once pushed
+    deleted: Vec<u64>,
+}
+
+async fn check_snapshots<F, Fut>(
+    con: &MultiplexedConnection,
+    key: &str,
+    current: &[Snapshot],
+    name: &str,
+    kind: &str,
+    prefix: &str,
+    is_deleted: F,
+) -> Result<SnapshotChanges>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let changes = snapshot::compare(con, key, current).await?;
+    let mut result = SnapshotChanges::default();
+
+    for (old, new) in changes.edited {
+        let mut body = vec![];
+        let diff = snapshot::diff(&old.text, &new.text);
+        if !diff.is_empty() {
+            body.push(sender::escape_html(&diff));
+        }
+        if old.media != new.media {
+            body.push(format!(
+                "图片从 {} 张变为 {} 张",
+                old.media.len(),
+                new.media.len()
+            ));
+        }
+        info!("用户「{}」编辑了{}：{}", name, kind, new.url);
+        result.telegram_sends.push((
+            format!("{}:{}:edit:{}", prefix, new.id, new.revision),
+            TelegramSend {
+                msg: format!(
+                    "<b>「{}」编辑了{}！</b>\n{}\n\n{}",
+                    sender::escape_html(name),
+                    kind,
+                    body.join("\n"),
+                    new.url
+                ),
+                reply_to: old.message_ids.clone(),
+                ..Default::default()
+            },
+        ));
+        result.snapshots.push(Some(new));
+    }
+
+    for i in changes.missing {
+        match is_deleted(i.id).await {
+            Ok(true) => {
+                info!("用户「{}」删除了{}：{}", name, kind, i.url);
+                result.telegram_sends.push((
+                    format!("{}:{}:delete", prefix, i.id),
+                    TelegramSend {
+                        msg: format!(
+                            "<b>「{}」删除了{}！</b>\n{}\n\n{}",
+                            sender::escape_html(name),
+                            kind,
+                            sender::escape_html(&i.text),
+                            i.url
+                        ),
+                        reply_to: i.message_ids.clone(),
+                        ..Default::default()
+                    },
+                ));
+                result.snapshots.push(None);
+                result.deleted.push(i.id);
+            }
+            // still there but not in the page any more, e.g. pushed down by a pinned post
+            Ok(false) => snapshot::remove(con, key, i.id).await?,
+            Err(e) => warn!("Can not check whether {} {} is deleted: {}", kind, i.id, e),
+        }
+    }
+
+    Ok(result)
+}
+
+fn dynamic_snapshot(dynamic: &BiliDynamicResult) -> Snapshot {
+    Snapshot::new(
+        dynamic.dynamic_id,
+        dynamic.plain_text(),
+        dynamic.pictures(),
+        dynamic.url(),
+    )
+}
+
+fn weibo_snapshot(mblog: &WeiboIndexDataCardMblog, uid: &str) -> Result<Snapshot> {
+    Ok(Snapshot::new(
+        mblog.id.parse()?,
+        mblog.plain_text(),
+        mblog.pictures(),
+        format!("https://weibo.com/{}/{}", uid, mblog.id),
+    ))
+}
+
 async fn check_and_send(
-    bot: Option<&AutoSend<Bot>>,
-    telegram_chat_id: Option<i64>,
-    mut telegram_sends: Vec<TelegramSend>,
-    client: &Client,
+    con: &MultiplexedConnection,
+    bot: Option<&Bot>,
+    chats: &[Route],
+    telegram_sends: Vec<(String, TelegramSend)>,
+) -> Result<()> {
+    let snapshots = vec![None; telegram_sends.len()];
+    check_and_send_snapshots(con, bot, chats, telegram_sends, "", snapshots).await
+}
+
+// notifications are written to the outbox and delivered by the tasker,
+// the snapshots are saved with the message ids after delivered.
+// each one comes with an event id naming its source, item and action
+async fn check_and_send_snapshots(
+    con: &MultiplexedConnection,
+    bot: Option<&Bot>,
+    chats: &[Route],
+    telegram_sends: Vec<(String, TelegramSend)>,
+    snapshot_key: &str,
+    snapshots: Vec<Option<Snapshot>>,
 ) -> Result<()> {
-    if let Some(bot) = bot {
-        if let Some(chat_id) = telegram_chat_id {
-            sender::send(&mut telegram_sends, bot, chat_id, client).await?;
+    // the oldest is sent first
+    for ((id, send), snapshot) in telegram_sends.into_iter().zip(snapshots).rev() {
+        let snapshot = snapshot.map(|x| (snapshot_key.to_string(), x));
+        if bot.is_none() || chats.is_empty() {
+            if let Some((key, snapshot)) = snapshot {
+                snapshot::save(con, &key, &snapshot).await?;
+            }
+            continue;
         }
+        outbox::push(con, id, send, chats, snapshot, None).await?;
     }
 
     Ok(())
 }
 
-fn timestamp_to_date(t: u64) -> Result<String> {
+pub fn timestamp_to_date(t: u64) -> Result<String> {
     let format = format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")?;
     let date = OffsetDateTime::from_unix_timestamp(t.try_into()?)?
         .to_offset(offset!(+8))
@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use redis::aio::MultiplexedConnection;
+use teloxide::{prelude::*, utils::command::BotCommands};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{
+    checker, sender,
+    subscription::{self, Kind, Route, KINDS},
+    weibo::WeiboClient,
+};
+
+lazy_static! {
+    // task label -> (last checked at, error of the last check)
+    static ref TASK_STATUS: DashMap<String, (u64, Option<String>)> = DashMap::new();
+}
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "小熊貓監視器支持以下命令：")]
+enum Command {
+    #[command(description = "显示帮助")]
+    Help,
+    #[command(description = "添加订阅，如 /subscribe bili_dynamic 1501380958")]
+    Subscribe(String),
+    #[command(description = "取消订阅，如 /unsubscribe live 22746343")]
+    Unsubscribe(String),
+    #[command(
+        description = "把订阅发送到指定的聊天，如 /route weibo 1234567890 -1001675012012/12:silent:noimage"
+    )]
+    Route(String),
+    #[command(
+        description = "不再把订阅发送到指定的聊天，如 /unroute weibo 1234567890 -1001675012012/12"
+    )]
+    Unroute(String),
+    #[command(description = "列出所有订阅")]
+    List,
+    #[command(description = "查看各个检查任务的状态")]
+    Status,
+}
+
+struct CommandArgs {
+    con: MultiplexedConnection,
+    weibo: WeiboClient,
+    admin_ids: Vec<UserId>,
+}
+
+pub async fn serve(bot: Bot, con: MultiplexedConnection, weibo: WeiboClient, admin_ids: Vec<u64>) {
+    if let Err(e) = bot.set_my_commands(Command::bot_commands()).await {
+        warn!("Can not set telegram bot commands: {}", e);
+    }
+
+    let args = Arc::new(CommandArgs {
+        con,
+        weibo,
+        admin_ids: admin_ids.into_iter().map(UserId).collect(),
+    });
+
+    let handler = Update::filter_message()
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(handle_command),
+        )
+        .branch(dptree::endpoint(handle_message));
+
+    sender::set_dispatching();
+    info!("Telegram command handler is started");
+
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![args])
+        .build()
+        .dispatch()
+        .await;
+}
+
+pub fn update_status(label: String, result: &Result<()>) {
+    let now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+    let error = result.as_ref().err().map(|e| e.to_string());
+    TASK_STATUS.insert(label, (now, error));
+}
+
+// drop the status of unsubscribed targets
+pub fn retain_status(labels: &[String]) {
+    TASK_STATUS.retain(|k, _| labels.contains(k));
+}
+
+// only the sender counts, the other members of the admin group are not admins
+fn is_admin(msg: &Message, admin_ids: &[UserId]) -> bool {
+    msg.from()
+        .map(|x| admin_ids.contains(&x.id))
+        .unwrap_or(false)
+}
+
+async fn handle_command(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    args: Arc<CommandArgs>,
+) -> Result<()> {
+    if !is_admin(&msg, &args.admin_ids) {
+        sender::reply_text(
+            &bot,
+            msg.chat.id.0,
+            msg.id.0,
+            "只有管理员才能使用这个命令！",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let reply = match cmd {
+        Command::Help => Command::descriptions().to_string(),
+        Command::Subscribe(target) => match subscribe(&args, &target).await {
+            Ok(reply) => reply,
+            Err(e) => format!("订阅失败：{}", e),
+        },
+        Command::Unsubscribe(target) => match unsubscribe(&args, &target).await {
+            Ok(reply) => reply,
+            Err(e) => format!("取消订阅失败：{}", e),
+        },
+        Command::Route(target) => match route(&args, &target).await {
+            Ok(reply) => reply,
+            Err(e) => format!("设置聊天失败：{}", e),
+        },
+        Command::Unroute(target) => match unroute(&args, &target).await {
+            Ok(reply) => reply,
+            Err(e) => format!("移除聊天失败：{}", e),
+        },
+        Command::List => match list(&args.con).await {
+            Ok(reply) => reply,
+            Err(e) => format!("获取订阅失败：{}", e),
+        },
+        Command::Status => status()?,
+    };
+
+    sender::reply_text(&bot, msg.chat.id.0, msg.id.0, &reply).await?;
+
+    Ok(())
+}
+
+async fn handle_message(msg: Message, args: Arc<CommandArgs>) -> Result<()> {
+    if !is_admin(&msg, &args.admin_ids) {
+        return Ok(());
+    }
+
+    if let Some(text) = msg.text() {
+        sender::reply_admin(text);
+    }
+
+    Ok(())
+}
+
+async fn subscribe(args: &CommandArgs, target: &str) -> Result<String> {
+    let (kind, id) = parse_target(target)?;
+    let id = resolve_id(args, kind, id).await?;
+
+    let reply = if subscription::add(&args.con, kind, &id).await? {
+        format!("已订阅 {} {}，将在下一轮检查时生效", kind, id)
+    } else {
+        format!("{} {} 已经订阅过了", kind, id)
+    };
+
+    Ok(reply)
+}
+
+async fn unsubscribe(args: &CommandArgs, target: &str) -> Result<String> {
+    let (kind, id) = parse_target(target)?;
+    let id = resolve_id(args, kind, id).await?;
+
+    let reply = if subscription::remove(&args.con, kind, &id).await? {
+        format!("已取消订阅 {} {}", kind, id)
+    } else {
+        format!("没有订阅 {} {}", kind, id)
+    };
+
+    Ok(reply)
+}
+
+async fn route(args: &CommandArgs, target: &str) -> Result<String> {
+    let (kind, id, route) = parse_route(target)?;
+    let id = resolve_id(args, kind, id).await?;
+
+    if !subscription::list(&args.con, kind).await?.contains(&id) {
+        bail!("{} {} is not subscribed!", kind, id);
+    }
+    subscription::add_route(&args.con, kind, &id, &route).await?;
+
+    Ok(format!("{} {} 将发送到 {}", kind, id, route))
+}
+
+async fn unroute(args: &CommandArgs, target: &str) -> Result<String> {
+    let (kind, id, route) = parse_route(target)?;
+    let id = resolve_id(args, kind, id).await?;
+
+    let reply = if subscription::remove_route(&args.con, kind, &id, &route).await? {
+        format!("{} {} 不再发送到 {}", kind, id, route.target())
+    } else {
+        format!("{} {} 没有发送到 {}", kind, id, route.target())
+    };
+
+    Ok(reply)
+}
+
+async fn resolve_id(args: &CommandArgs, kind: Kind, id: String) -> Result<String> {
+    match kind {
+        Kind::Weibo => args.weibo.resolve_uid(&id).await,
+        _ => Ok(id),
+    }
+}
+
+async fn list(con: &MultiplexedConnection) -> Result<String> {
+    let mut lines = vec![];
+    for kind in KINDS {
+        for id in subscription::list(con, *kind).await? {
+            let routes = subscription::list_routes(con, *kind, &id).await?;
+            if routes.is_empty() {
+                lines.push(format!("{} {}", kind, id));
+            } else {
+                let routes = routes.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                lines.push(format!("{} {} -> {}", kind, id, routes.join(", ")));
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        return Ok("还没有任何订阅".to_string());
+    }
+
+    Ok(lines.join("\n"))
+}
+
+fn status() -> Result<String> {
+    let mut status = TASK_STATUS
+        .iter()
+        .map(|x| (x.key().clone(), x.value().clone()))
+        .collect::<Vec<_>>();
+    status.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    if status.is_empty() {
+        return Ok("还没有完成过任何检查".to_string());
+    }
+
+    let mut lines = vec![];
+    for (label, (checked_at, error)) in status {
+        let checked_at = checker::timestamp_to_date(checked_at)?;
+        match error {
+            Some(e) => lines.push(format!("❌ {} ({})\n{}", label, checked_at, e)),
+            None => lines.push(format!("✅ {} ({})", label, checked_at)),
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+fn parse_target(target: &str) -> Result<(Kind, String)> {
+    let mut split = target.split_whitespace();
+    match (split.next(), split.next(), split.next()) {
+        (Some(kind), Some(id), None) => parse_kind_and_id(kind, id),
+        _ => bail!("Usage: <type> <id>"),
+    }
+}
+
+fn parse_route(target: &str) -> Result<(Kind, String, Route)> {
+    let mut split = target.split_whitespace();
+    match (split.next(), split.next(), split.next(), split.next()) {
+        (Some(kind), Some(id), Some(route), None) => {
+            let (kind, id) = parse_kind_and_id(kind, id)?;
+            Ok((kind, id, route.parse::<Route>()?))
+        }
+        _ => bail!("Usage: <type> <id> <chat_id>[/<thread_id>][:silent][:noimage]"),
+    }
+}
+
+fn parse_kind_and_id(kind: &str, id: &str) -> Result<(Kind, String)> {
+    let kind = kind.parse::<Kind>()?;
+
+    // weibo target may be a profile url, it is resolved later
+    if kind != Kind::Weibo && id.parse::<u64>().is_err() {
+        bail!("{} is not a number!", id);
+    }
+
+    Ok((kind, id.to_string()))
+}
+
+#[test]
+fn test_parse_target() {
+    assert_eq!(
+        parse_target("bili_dynamic 1501380958").unwrap(),
+        (Kind::BiliDynamic, "1501380958".to_string())
+    );
+    assert_eq!(
+        parse_target(" weibo  https://weibo.com/u/1234567890 ").unwrap(),
+        (Kind::Weibo, "https://weibo.com/u/1234567890".to_string())
+    );
+    assert!(parse_target("live abc").is_err());
+    assert!(parse_target("live").is_err());
+    assert!(parse_target("live 1 2").is_err());
+
+    let (kind, id, route) = parse_route("live 22746343 -1001675012012/12:silent").unwrap();
+    assert_eq!((kind, id.as_str()), (Kind::Live, "22746343"));
+    assert_eq!(route.to_string(), "-1001675012012/12:silent");
+    assert!(parse_route("live 22746343").is_err());
+}
@@ -0,0 +1,153 @@
+use std::cmp::Reverse;
+
+use anyhow::Result;
+use reqwest::{header::HeaderMap, Client};
+use serde::Deserialize;
+
+use crate::{dynamic::CommentArea, risk_control};
+
+#[derive(Debug, Deserialize)]
+struct ReplyMain {
+    data: ReplyMainData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplyMainData {
+    replies: Option<Vec<Reply>>,
+    upper: Option<ReplyUpper>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplyUpper {
+    top: Option<Reply>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplySub {
+    data: ReplySubData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplySubData {
+    replies: Option<Vec<Reply>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Reply {
+    rpid: u64,
+    mid: u64,
+    ctime: u64,
+    member: ReplyMember,
+    content: ReplyContent,
+    replies: Option<Vec<Reply>>,
+    up_action: Option<ReplyUpAction>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct ReplyMember {
+    uname: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct ReplyContent {
+    message: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct ReplyUpAction {
+    reply: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BiliReply {
+    pub rpid: u64,
+    pub uname: String,
+    pub message: String,
+    pub ctime: u64,
+    pub pinned: bool,
+    pub root: Option<(String, String)>,
+}
+
+impl BiliReply {
+    fn new(reply: &Reply, pinned: bool, root: Option<&Reply>) -> Self {
+        BiliReply {
+            rpid: reply.rpid,
+            uname: reply.member.uname.clone(),
+            message: reply.content.message.clone(),
+            ctime: reply.ctime,
+            pinned,
+            root: root.map(|x| (x.member.uname.clone(), x.content.message.clone())),
+        }
+    }
+}
+
+pub async fn get_watched_replies(
+    uid: u64,
+    area: CommentArea,
+    client: &Client,
+) -> Result<Vec<BiliReply>> {
+    let mut headers = HeaderMap::new();
+    headers.append("Referer", "https://www.bilibili.com".parse()?);
+    let r: ReplyMain = risk_control::get_json(
+        client,
+        &format!(
+            "https://api.bilibili.com/x/v2/reply?type={}&oid={}&sort=0&pn=1&ps=20",
+            area.comment_type, area.oid
+        ),
+        headers.clone(),
+    )
+    .await?;
+
+    let mut result = vec![];
+
+    if let Some(top) = r.data.upper.and_then(|x| x.top) {
+        result.push(BiliReply::new(&top, true, None));
+    }
+
+    for root in r.data.replies.unwrap_or_default() {
+        if root.mid == uid {
+            result.push(BiliReply::new(&root, false, None));
+        }
+
+        let sub_replies = if root.up_action.as_ref().map(|x| x.reply) == Some(true) {
+            let r: ReplySub = risk_control::get_json(
+                client,
+                &format!(
+                    "https://api.bilibili.com/x/v2/reply/reply?type={}&oid={}&root={}&pn=1&ps=20",
+                    area.comment_type, area.oid, root.rpid
+                ),
+                headers.clone(),
+            )
+            .await?;
+            r.data.replies.unwrap_or_default()
+        } else {
+            root.replies.clone().unwrap_or_default()
+        };
+
+        for i in sub_replies.iter().filter(|x| x.mid == uid) {
+            result.push(BiliReply::new(i, false, Some(&root)));
+        }
+    }
+
+    result.sort_by_key(|x| x.rpid);
+    result.dedup_by_key(|x| x.rpid);
+    result.sort_by_key(|x| Reverse(x.ctime));
+
+    Ok(result)
+}
+
+#[tokio::test]
+async fn test() {
+    let client = Client::new();
+    let replies = get_watched_replies(
+        1501380958,
+        CommentArea {
+            oid: 637411128,
+            comment_type: 11,
+        },
+        &client,
+    )
+    .await
+    .unwrap();
+    dbg!(replies);
+}
@@ -1,7 +1,15 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use reqwest::{header::HeaderMap, Client};
 use serde::Deserialize;
-use tracing::error;
+use tracing::warn;
+
+use crate::{
+    risk_control,
+    sender::{escape_attr, escape_html},
+};
+
+// 4101131: the dynamic is deleted, -404: nothing here
+const DYNAMIC_DELETED_CODES: &[i64] = &[4101131, -404];
 
 #[derive(Debug, Deserialize, Clone)]
 struct BiliDynamic {
@@ -10,201 +18,832 @@ struct BiliDynamic {
 
 #[derive(Debug, Deserialize, Clone)]
 struct Data {
-    cards: Vec<Card>,
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Item {
+    id_str: Option<String>,
+    #[serde(rename = "type")]
+    item_type: String,
+    modules: Modules,
+    orig: Option<Box<Item>>,
+    basic: Option<Basic>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Basic {
+    comment_id_str: String,
+    comment_type: i64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Modules {
+    module_author: ModuleAuthor,
+    module_dynamic: Option<ModuleDynamic>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct Card {
-    desc: Desc,
-    card: String,
-    card_dese: Option<CardInner>,
+struct ModuleAuthor {
+    mid: Option<u64>,
+    name: Option<String>,
+    pub_ts: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct ModuleDynamic {
+    desc: Option<Desc>,
+    major: Option<Major>,
+    additional: Option<Additional>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 struct Desc {
-    dynamic_id: u64,
-    timestamp: u64,
-    user_profile: Option<UserProfile>,
+    text: String,
+    rich_text_nodes: Option<Vec<RichTextNode>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RichTextNode {
+    #[serde(rename = "type")]
+    node_type: String,
+    text: String,
+    rid: Option<String>,
+    jump_url: Option<String>,
+    emoji: Option<RichTextNodeEmoji>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RichTextNodeEmoji {
+    icon_url: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Major {
+    archive: Option<MajorArchive>,
+    ugc_season: Option<MajorArchive>,
+    draw: Option<MajorDraw>,
+    article: Option<MajorArticle>,
+    opus: Option<MajorOpus>,
+    music: Option<MajorMusic>,
+    courses: Option<MajorCourses>,
+    live: Option<MajorLive>,
+    live_rcmd: Option<MajorLiveRcmd>,
+    common: Option<MajorCommon>,
+    pgc: Option<MajorCommon>,
+    none: Option<MajorNone>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct MajorArchive {
+    bvid: Option<String>,
+    title: String,
+    desc: Option<String>,
+    cover: Option<String>,
+    duration_text: Option<String>,
+    jump_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct MajorDraw {
+    items: Vec<MajorDrawItem>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct UserProfile {
-    info: UserProfileInfo,
+struct MajorDrawItem {
+    src: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct UserProfileInfo {
-    uid: u64,
-    uname: String,
+struct MajorArticle {
+    id: u64,
+    title: String,
+    desc: Option<String>,
+    covers: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct CardInner {
-    user: Option<User>,
-    item: Option<CardItem>,
+struct MajorOpus {
     title: Option<String>,
-    short_link: Option<String>,
-    short_link_v2: Option<String>,
-    pic: Option<String>,
-    origin: Option<String>,
-    origin_dese: Box<Option<CardInner>>,
-    origin_user: Option<UserProfile>,
+    summary: Option<Desc>,
+    pics: Option<Vec<MajorOpusPic>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct CardItem {
-    description: Option<String>,
-    pictures: Option<Vec<Picture>>,
-    content: Option<String>,
+struct MajorOpusPic {
+    url: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct User {
-    name: Option<String>,
-    uname: Option<String>,
-    uid: u64,
+struct MajorMusic {
+    title: String,
+    cover: Option<String>,
+    label: Option<String>,
+    jump_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct MajorCourses {
+    title: String,
+    sub_title: Option<String>,
+    desc: Option<String>,
+    cover: Option<String>,
+    jump_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct MajorLive {
+    title: String,
+    cover: Option<String>,
+    jump_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct MajorLiveRcmd {
+    content: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct LiveRcmdContent {
+    live_play_info: LiveRcmdPlayInfo,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct LiveRcmdPlayInfo {
+    title: String,
+    cover: Option<String>,
+    link: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
-pub struct Picture {
-    pub img_src: Option<String>,
+struct MajorCommon {
+    title: String,
+    desc: Option<String>,
+    cover: Option<String>,
+    jump_url: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+struct MajorNone {
+    tips: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct Additional {
+    reserve: Option<AdditionalReserve>,
+    vote: Option<AdditionalVote>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct AdditionalReserve {
+    rid: u64,
+    title: String,
+    desc1: Option<AdditionalReserveDesc>,
+    jump_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct AdditionalReserveDesc {
+    text: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct AdditionalVote {
+    vote_id: u64,
+    desc: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteInfo {
+    data: VoteInfoData,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteInfoData {
+    info: VoteInfoDataInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteInfoDataInfo {
+    options: Vec<VoteInfoOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VoteInfoOption {
+    desc: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum DynamicContent {
+    Text,
+    Draw {
+        pictures: Vec<String>,
+    },
+    Video {
+        bvid: Option<String>,
+        title: String,
+        desc: Option<String>,
+        cover: Option<String>,
+        duration: Option<String>,
+        url: Option<String>,
+    },
+    Article {
+        id: u64,
+        title: String,
+        summary: Option<String>,
+        covers: Vec<String>,
+    },
+    Forward {
+        origin: Option<Box<BiliDynamicResult>>,
+    },
+    Music {
+        title: String,
+        label: Option<String>,
+        cover: Option<String>,
+        url: Option<String>,
+    },
+    Course {
+        title: String,
+        subtitle: Option<String>,
+        desc: Option<String>,
+        cover: Option<String>,
+        url: Option<String>,
+    },
+    Live {
+        title: String,
+        cover: Option<String>,
+        url: Option<String>,
+    },
+    Common {
+        title: String,
+        desc: Option<String>,
+        cover: Option<String>,
+        url: Option<String>,
+    },
+    Deleted {
+        tips: Option<String>,
+    },
+    Unknown {
+        dynamic_type: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum DynamicAdditional {
+    LiveReservation {
+        rid: u64,
+        title: String,
+        desc: Option<String>,
+        url: Option<String>,
+    },
+    Vote {
+        vote_id: u64,
+        title: String,
+        options: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RichText {
+    Text(String),
+    Emoji { text: String, url: String },
+    At { text: String, uid: String },
+    Topic { text: String, url: String },
+    Link { text: String, url: String },
+}
+
+const EMOJI_MAP: &[(&str, &str)] = &[
+    ("[doge]", "🐶"),
+    ("[微笑]", "🙂"),
+    ("[OK]", "👌"),
+    ("[星星眼]", "🤩"),
+    ("[喜欢]", "😍"),
+    ("[害羞]", "😊"),
+    ("[呲牙]", "😁"),
+    ("[笑哭]", "😂"),
+    ("[大哭]", "😭"),
+    ("[捂脸]", "🤦"),
+    ("[生气]", "😠"),
+    ("[惊讶]", "😲"),
+    ("[疑惑]", "🤔"),
+    ("[思考]", "🤔"),
+    ("[墨镜]", "😎"),
+    ("[吃瓜]", "🍉"),
+    ("[酸了]", "🍋"),
+    ("[点赞]", "👍"),
+    ("[鼓掌]", "👏"),
+    ("[抱拳]", "🙏"),
+    ("[爱心]", "❤️"),
+    ("[打call]", "📣"),
+];
+
+impl RichText {
+    fn from_nodes(nodes: Vec<RichTextNode>) -> Vec<RichText> {
+        nodes
+            .into_iter()
+            .map(|x| match x.node_type.as_str() {
+                "RICH_TEXT_NODE_TYPE_EMOJI" => match x.emoji {
+                    Some(emoji) => RichText::Emoji {
+                        text: x.text,
+                        url: emoji.icon_url,
+                    },
+                    None => RichText::Text(x.text),
+                },
+                "RICH_TEXT_NODE_TYPE_AT" => match x.rid {
+                    Some(uid) => RichText::At { text: x.text, uid },
+                    None => RichText::Text(x.text),
+                },
+                "RICH_TEXT_NODE_TYPE_TOPIC" => match full_url(x.jump_url) {
+                    Some(url) => RichText::Topic { text: x.text, url },
+                    None => RichText::Text(x.text),
+                },
+                _ => match full_url(x.jump_url) {
+                    Some(url) => RichText::Link { text: x.text, url },
+                    None => RichText::Text(x.text),
+                },
+            })
+            .collect()
+    }
+
+    fn emoji_unicode(text: &str) -> Option<&'static str> {
+        EMOJI_MAP.iter().find(|(k, _)| *k == text).map(|(_, v)| *v)
+    }
+
+    fn to_html(nodes: &[RichText]) -> String {
+        nodes
+            .iter()
+            .map(|x| match x {
+                RichText::Text(text) => escape_html(text),
+                RichText::Emoji { text, url } => match Self::emoji_unicode(text) {
+                    Some(emoji) => emoji.to_string(),
+                    None => format!("<a href=\"{}\">{}</a>", escape_attr(url), escape_html(text)),
+                },
+                RichText::At { text, uid } => format!(
+                    "<a href=\"https://space.bilibili.com/{}\">{}</a>",
+                    escape_attr(uid),
+                    escape_html(text)
+                ),
+                RichText::Topic { text, url } | RichText::Link { text, url } => {
+                    format!("<a href=\"{}\">{}</a>", escape_attr(url), escape_html(text))
+                }
+            })
+            .collect()
+    }
+
+    fn to_plain(nodes: &[RichText]) -> String {
+        nodes
+            .iter()
+            .map(|x| match x {
+                RichText::Emoji { text, .. } => Self::emoji_unicode(text).unwrap_or(text),
+                RichText::Text(text)
+                | RichText::At { text, .. }
+                | RichText::Topic { text, .. }
+                | RichText::Link { text, .. } => text,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct BiliDynamicResult {
     pub user: Option<String>,
     pub uid: Option<u64>,
-    pub description: Option<String>,
-    pub picture: Option<Vec<Picture>>,
+    pub text: Option<String>,
+    pub rich_text: Vec<RichText>,
+    pub content: DynamicContent,
+    pub additional: Option<DynamicAdditional>,
+    pub comment_area: Option<CommentArea>,
     pub dynamic_id: u64,
     pub timestamp: u64,
 }
 
-fn trans(c: CardInner, desc: Desc) -> BiliDynamicResult {
-    let c_user_clone = c.user.clone();
-    let c_user_clone_2 = c.user.clone();
-    let desc_user_profile_clone = desc.user_profile.clone();
+#[derive(Debug, Clone, Copy)]
+pub struct CommentArea {
+    pub oid: u64,
+    pub comment_type: i64,
+}
 
-    let user = if let Some(u) = c.user.and_then(|x| x.name) {
-        Some(u)
-    } else if let Some(u) = c_user_clone.and_then(|x| x.uname) {
-        Some(u)
-    } else {
-        desc.user_profile.map(|x| x.info.uname)
-    };
-    let uid = if let Some(id) = c_user_clone_2.map(|x| x.uid) {
-        Some(id)
-    } else {
-        desc_user_profile_clone.map(|x| x.info.uid)
-    };
-    let item_clone = c.item.clone();
-    let item_clone_2 = c.item.clone();
-    let description = if let Some(desc) = c.item.and_then(|x| x.description) {
-        Some(desc)
-    } else if let Some(content) = item_clone.and_then(|x| x.content) {
-        if let Some(origin_desc) = c
-            .origin_dese
-            .clone()
-            .and_then(|x| x.item.and_then(|x| x.description))
-        {
-            if let Some(origin_user) = c.origin_user.map(|x| x.info.uname) {
-                Some(format!("{} // {}: {}", content, origin_user, origin_desc))
-            } else {
-                Some(format!("{} // {}", content, origin_desc))
-            }
-        } else if let Some(origin_title) = c.origin_dese.clone().and_then(|x| x.title) {
-            Some(format!(
-                "{} // {}{}",
-                content,
-                origin_title,
-                if let Some(link) = c.origin_dese.clone().and_then(|x| x.short_link_v2) {
-                    format!("({})", link)
-                } else if let Some(link) = c.origin_dese.clone().and_then(|x| x.short_link) {
-                    format!("({})", link)
-                } else {
-                    "".to_string()
-                }
-            ))
+impl BiliDynamicResult {
+    pub fn action(&self) -> &'static str {
+        match (&self.content, &self.additional) {
+            (DynamicContent::Forward { .. }, _) => "转发了动态",
+            (DynamicContent::Video { .. }, _) => "投稿了视频",
+            (DynamicContent::Article { .. }, _) => "发布了专栏",
+            (DynamicContent::Music { .. }, _) => "投稿了音频",
+            (DynamicContent::Course { .. }, _) => "发布了课程",
+            (DynamicContent::Live { .. }, _) => "正在直播",
+            (_, Some(DynamicAdditional::LiveReservation { .. })) => "发布了直播预约",
+            (_, Some(DynamicAdditional::Vote { .. })) => "发起了投票",
+            (DynamicContent::Draw { .. }, _) => "发布了图文动态",
+            _ => "有新动态啦",
+        }
+    }
+
+    pub fn plain_text(&self) -> String {
+        if self.rich_text.is_empty() {
+            self.text.clone().unwrap_or_default()
         } else {
-            Some(content)
+            RichText::to_plain(&self.rich_text)
         }
-    } else {
-        c.title.map(|x| {
-            format!(
-                "{}\n{}",
-                x,
-                if let Some(url) = c.short_link_v2 {
-                    url
-                } else if let Some(url) = c.short_link {
-                    url
-                } else {
-                    "".to_string()
+    }
+
+    pub fn render(&self) -> String {
+        let mut s = if self.rich_text.is_empty() {
+            self.text.as_deref().map(escape_html).unwrap_or_default()
+        } else {
+            RichText::to_html(&self.rich_text)
+        };
+
+        let body = match &self.content {
+            DynamicContent::Text | DynamicContent::Draw { .. } => None,
+            DynamicContent::Video {
+                title,
+                desc,
+                duration,
+                ..
+            } => Some(format!(
+                "<b>{}</b>{}{}",
+                escape_html(title),
+                duration
+                    .as_ref()
+                    .map(|x| format!(" ({})", x))
+                    .unwrap_or_default(),
+                desc.as_ref()
+                    .filter(|x| !x.is_empty() && Some(*x) != self.text.as_ref())
+                    .map(|x| format!("\n{}", escape_html(x)))
+                    .unwrap_or_default()
+            )),
+            DynamicContent::Article { title, summary, .. } => Some(format!(
+                "<b>{}</b>{}",
+                escape_html(title),
+                summary
+                    .as_ref()
+                    .map(|x| format!("\n{}", escape_html(x)))
+                    .unwrap_or_default()
+            )),
+            DynamicContent::Forward { origin } => Some(match origin {
+                Some(origin) => format!(
+                    "// @{}: {}",
+                    escape_html(origin.user.as_deref().unwrap_or("未知用户")),
+                    origin.render()
+                ),
+                None => "// 源动态不可见".to_string(),
+            }),
+            DynamicContent::Music { title, label, .. } => Some(format!(
+                "<b>{}</b>{}",
+                escape_html(title),
+                label
+                    .as_ref()
+                    .map(|x| format!(" - {}", escape_html(x)))
+                    .unwrap_or_default()
+            )),
+            DynamicContent::Course {
+                title,
+                subtitle,
+                desc,
+                ..
+            } => Some(format!(
+                "<b>{}</b>{}{}",
+                escape_html(title),
+                subtitle
+                    .as_ref()
+                    .map(|x| format!("\n{}", escape_html(x)))
+                    .unwrap_or_default(),
+                desc.as_ref()
+                    .map(|x| format!("\n{}", escape_html(x)))
+                    .unwrap_or_default()
+            )),
+            DynamicContent::Live { title, .. } => Some(format!("<b>{}</b>", escape_html(title))),
+            DynamicContent::Common { title, desc, .. } => Some(format!(
+                "<b>{}</b>{}",
+                escape_html(title),
+                desc.as_ref()
+                    .map(|x| format!("\n{}", escape_html(x)))
+                    .unwrap_or_default()
+            )),
+            DynamicContent::Deleted { tips } => {
+                Some(escape_html(tips.as_deref().unwrap_or("源动态已被作者删除")))
+            }
+            DynamicContent::Unknown { dynamic_type } => {
+                Some(format!("暂不支持的动态类型：{}", dynamic_type))
+            }
+        };
+
+        if let Some(mut body) = body {
+            if let Some(url) = self.content_url() {
+                body.push_str(&format!("\n{}", escape_html(&url)));
+            }
+            push_paragraph(&mut s, &body);
+        }
+
+        if let Some(additional) = &self.additional {
+            let additional = match additional {
+                DynamicAdditional::LiveReservation {
+                    rid,
+                    title,
+                    desc,
+                    url,
+                } => format!(
+                    "<b>{}</b>{}\n{}",
+                    escape_html(title),
+                    desc.as_ref()
+                        .map(|x| format!("\n{}", escape_html(x)))
+                        .unwrap_or_default(),
+                    escape_html(url.as_deref().unwrap_or(&format!(
+                        "https://space.bilibili.com/{}/reservation/{}",
+                        self.uid.unwrap_or_default(),
+                        rid
+                    )))
+                ),
+                DynamicAdditional::Vote { title, options, .. } => {
+                    let mut s = format!("<b>投票：{}</b>", escape_html(title));
+                    for (i, option) in options.iter().enumerate() {
+                        s.push_str(&format!("\n{}. {}", i + 1, escape_html(option)));
+                    }
+                    s
                 }
-            )
+            };
+            push_paragraph(&mut s, &additional);
+        }
+
+        s
+    }
+
+    fn content_url(&self) -> Option<String> {
+        match &self.content {
+            DynamicContent::Video { bvid, url, .. } => url.clone().or_else(|| {
+                bvid.as_ref()
+                    .map(|x| format!("https://www.bilibili.com/video/{}", x))
+            }),
+            DynamicContent::Article { id, .. } if *id != 0 => {
+                Some(format!("https://www.bilibili.com/read/cv{}", id))
+            }
+            DynamicContent::Music { url, .. }
+            | DynamicContent::Course { url, .. }
+            | DynamicContent::Live { url, .. }
+            | DynamicContent::Common { url, .. } => url.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn pictures(&self) -> Vec<String> {
+        match &self.content {
+            DynamicContent::Draw { pictures } => pictures.clone(),
+            DynamicContent::Article { covers, .. } => covers.clone(),
+            DynamicContent::Forward { origin } => {
+                origin.as_ref().map(|x| x.pictures()).unwrap_or_default()
+            }
+            DynamicContent::Video { cover, .. }
+            | DynamicContent::Music { cover, .. }
+            | DynamicContent::Course { cover, .. }
+            | DynamicContent::Live { cover, .. }
+            | DynamicContent::Common { cover, .. } => cover.iter().cloned().collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://t.bilibili.com/{}", self.dynamic_id)
+    }
+}
+
+fn push_paragraph(s: &mut String, paragraph: &str) {
+    if !s.is_empty() {
+        s.push_str("\n\n");
+    }
+    s.push_str(paragraph);
+}
+
+fn full_url(url: Option<String>) -> Option<String> {
+    url.map(|x| {
+        if x.starts_with("//") {
+            format!("https:{}", x)
+        } else {
+            x
+        }
+    })
+}
+
+fn trans(item: Item) -> BiliDynamicResult {
+    let author = item.modules.module_author;
+    let comment_area = item.basic.and_then(|x| {
+        x.comment_id_str.parse::<u64>().ok().map(|oid| CommentArea {
+            oid,
+            comment_type: x.comment_type,
         })
-    };
-    let dynamic_id = desc.dynamic_id;
-    let time = desc.timestamp;
-    let picture = if let Some(pics) = item_clone_2.and_then(|x| x.pictures) {
-        Some(pics)
-    } else if let Some(pic) = c.pic {
-        Some(vec![Picture { img_src: Some(pic) }])
+    });
+    let module_dynamic = item.modules.module_dynamic;
+    let dynamic_id = item
+        .id_str
+        .as_deref()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or_default();
+
+    let major = module_dynamic.as_ref().and_then(|x| x.major.clone());
+    let mut desc = module_dynamic.as_ref().and_then(|x| x.desc.clone());
+
+    let content = if item.item_type == "DYNAMIC_TYPE_FORWARD" {
+        DynamicContent::Forward {
+            origin: item.orig.map(|x| Box::new(trans(*x))),
+        }
+    } else if let Some(major) = major {
+        if let Some(archive) = major.archive.or(major.ugc_season) {
+            DynamicContent::Video {
+                bvid: archive.bvid,
+                title: archive.title,
+                desc: archive.desc,
+                cover: archive.cover,
+                duration: archive.duration_text,
+                url: full_url(archive.jump_url),
+            }
+        } else if let Some(draw) = major.draw {
+            DynamicContent::Draw {
+                pictures: draw.items.into_iter().map(|x| x.src).collect(),
+            }
+        } else if let Some(article) = major.article {
+            DynamicContent::Article {
+                id: article.id,
+                title: article.title,
+                summary: article.desc,
+                covers: article.covers.unwrap_or_default(),
+            }
+        } else if let Some(opus) = major.opus {
+            if desc.is_none() {
+                desc = opus.summary;
+            }
+            let pictures = opus
+                .pics
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| x.url)
+                .collect::<Vec<_>>();
+            match opus.title {
+                Some(title) if item.item_type == "DYNAMIC_TYPE_ARTICLE" => {
+                    DynamicContent::Article {
+                        id: 0,
+                        title,
+                        summary: None,
+                        covers: pictures,
+                    }
+                }
+                _ if pictures.is_empty() => DynamicContent::Text,
+                _ => DynamicContent::Draw { pictures },
+            }
+        } else if let Some(music) = major.music {
+            DynamicContent::Music {
+                title: music.title,
+                label: music.label,
+                cover: music.cover,
+                url: full_url(music.jump_url),
+            }
+        } else if let Some(courses) = major.courses {
+            DynamicContent::Course {
+                title: courses.title,
+                subtitle: courses.sub_title,
+                desc: courses.desc,
+                cover: courses.cover,
+                url: full_url(courses.jump_url),
+            }
+        } else if let Some(live) = major.live {
+            DynamicContent::Live {
+                title: live.title,
+                cover: live.cover,
+                url: full_url(live.jump_url),
+            }
+        } else if let Some(live_rcmd) = major.live_rcmd {
+            match serde_json::from_str::<LiveRcmdContent>(&live_rcmd.content) {
+                Ok(content) => DynamicContent::Live {
+                    title: content.live_play_info.title,
+                    cover: content.live_play_info.cover,
+                    url: full_url(content.live_play_info.link),
+                },
+                Err(e) => {
+                    warn!("Can not parse live recommend content: {}", e);
+                    DynamicContent::Unknown {
+                        dynamic_type: item.item_type,
+                    }
+                }
+            }
+        } else if let Some(common) = major.common.or(major.pgc) {
+            DynamicContent::Common {
+                title: common.title,
+                desc: common.desc,
+                cover: common.cover,
+                url: full_url(common.jump_url),
+            }
+        } else if let Some(none) = major.none {
+            DynamicContent::Deleted { tips: none.tips }
+        } else {
+            DynamicContent::Unknown {
+                dynamic_type: item.item_type,
+            }
+        }
+    } else if item.item_type == "DYNAMIC_TYPE_WORD" {
+        DynamicContent::Text
+    } else if item.item_type == "DYNAMIC_TYPE_NONE" {
+        DynamicContent::Deleted { tips: None }
     } else {
-        c.origin_dese.and_then(|x| x.item.and_then(|x| x.pictures))
+        DynamicContent::Unknown {
+            dynamic_type: item.item_type,
+        }
+    };
+
+    let additional = module_dynamic.and_then(|x| x.additional).and_then(|x| {
+        if let Some(reserve) = x.reserve {
+            Some(DynamicAdditional::LiveReservation {
+                rid: reserve.rid,
+                title: reserve.title,
+                desc: reserve.desc1.map(|x| x.text),
+                url: full_url(reserve.jump_url),
+            })
+        } else {
+            x.vote.map(|vote| DynamicAdditional::Vote {
+                vote_id: vote.vote_id,
+                title: vote.desc,
+                options: vec![],
+            })
+        }
+    });
+
+    let (text, rich_text) = match desc {
+        Some(desc) => (
+            Some(desc.text).filter(|x| !x.is_empty()),
+            RichText::from_nodes(desc.rich_text_nodes.unwrap_or_default()),
+        ),
+        None => (None, vec![]),
     };
 
     BiliDynamicResult {
-        user,
-        uid,
-        description,
-        picture,
+        user: author.name,
+        uid: author.mid,
+        text,
+        rich_text,
+        content,
+        additional,
+        comment_area,
         dynamic_id,
-        timestamp: time,
+        timestamp: author.pub_ts.unwrap_or_default(),
+    }
+}
+
+// the options are not in the feed, only fetched for the new dynamics
+pub async fn get_vote_options(vote_id: u64, client: &Client) -> Result<Vec<String>> {
+    let vote: VoteInfo = risk_control::get_json(
+        client,
+        &format!(
+            "https://api.vc.bilibili.com/vote_svr/v1/vote_svr/vote_info?vote_id={}",
+            vote_id
+        ),
+        HeaderMap::new(),
+    )
+    .await?;
+
+    Ok(vote.data.info.options.into_iter().map(|x| x.desc).collect())
+}
+
+pub async fn is_deleted(dynamic_id: u64, client: &Client) -> Result<bool> {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        "Referer",
+        (format!("https://t.bilibili.com/{}", dynamic_id)).parse()?,
+    );
+    let code = risk_control::get_code(
+        client,
+        &format!(
+            "https://api.bilibili.com/x/polymer/web-dynamic/v1/detail?id={}",
+            dynamic_id
+        ),
+        headers,
+    )
+    .await?;
+
+    match code {
+        0 => Ok(false),
+        c if DYNAMIC_DELETED_CODES.contains(&c) => Ok(true),
+        c => bail!("Can not get detail of dynamic {}: code {}", dynamic_id, c),
     }
 }
 
 pub async fn get_ailurus_dynamic(uid: u64, client: &Client) -> Result<Vec<BiliDynamicResult>> {
-    let mut result = vec![];
     let mut headers = HeaderMap::new();
     headers.append(
         "Referer",
-        (format!("https://space.bilibili.com/{}", uid)).parse()?,
+        (format!("https://space.bilibili.com/{}/dynamic", uid)).parse()?,
     );
-    let mut r = client
-        .get(format!(
-            "https://api.vc.bilibili.com/dynamic_svr/v1/dynamic_svr/space_history?host_uid={}",
+    let r: BiliDynamic = risk_control::get_json(
+        client,
+        &format!(
+            "https://api.bilibili.com/x/polymer/web-dynamic/v1/feed/space?host_mid={}",
             &uid
-        ))
-        .headers(headers)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<BiliDynamic>()
-        .await?;
-
-    let cards = r.data.cards.to_owned();
-
-    for (i, c) in cards.iter().enumerate() {
-        let json = serde_json::from_str::<CardInner>(&c.card);
-        if json.is_err() {
-            error!("{} {:?} {:?}", i, c, &json);
-        }
-        if let Ok(json) = json {
-            r.data.cards[i].card_dese = Some(json);
-        } else {
-            continue;
-        }
-        if let Some(mut card_dese) = r.data.cards[i].card_dese.to_owned() {
-            if let Some(origin) = &card_dese.origin {
-                let s: CardInner = serde_json::from_str(origin)?;
-                card_dese.origin_dese = Box::new(Some(s));
-                r.data.cards[i].card_dese = Some(card_dese);
-            }
-        }
-        result.push(trans(
-            r.data.cards[i].card_dese.clone().unwrap(),
-            r.data.cards[i].clone().desc,
-        ));
+        ),
+        headers,
+    )
+    .await?;
+
+    let result = r.data.items.into_iter().map(trans).collect::<Vec<_>>();
+
+    if result.is_empty() {
+        return Err(anyhow!("{} dynamic is empty!", uid));
     }
 
     Ok(result)
@@ -216,3 +855,79 @@ async fn test() {
     let json = get_ailurus_dynamic(1501380958, &client).await.unwrap();
     dbg!(json[4].to_owned());
 }
+
+#[test]
+fn test_render_forward() {
+    let json = r#"{
+        "id_str": "2",
+        "type": "DYNAMIC_TYPE_FORWARD",
+        "modules": {
+            "module_author": { "mid": 1, "name": "小熊猫", "pub_ts": 1700000100 },
+            "module_dynamic": { "desc": { "text": "转发 <3" }, "major": null, "additional": null }
+        },
+        "orig": {
+            "id_str": "1",
+            "type": "DYNAMIC_TYPE_AV",
+            "modules": {
+                "module_author": { "mid": 2, "name": "UP", "pub_ts": 1700000000 },
+                "module_dynamic": {
+                    "desc": null,
+                    "major": {
+                        "type": "MAJOR_TYPE_ARCHIVE",
+                        "archive": {
+                            "bvid": "BV1xx411c7mD",
+                            "title": "视频标题",
+                            "desc": "简介",
+                            "cover": "https://i0.hdslb.com/cover.jpg",
+                            "duration_text": "10:00",
+                            "jump_url": "//www.bilibili.com/video/BV1xx411c7mD"
+                        }
+                    },
+                    "additional": null
+                }
+            }
+        }
+    }"#;
+    let result = trans(serde_json::from_str(json).unwrap());
+
+    assert_eq!(result.action(), "转发了动态");
+    assert_eq!(
+        result.render(),
+        "转发 &lt;3\n\n// @UP: <b>视频标题</b> (10:00)\n简介\nhttps://www.bilibili.com/video/BV1xx411c7mD"
+    );
+    assert_eq!(result.pictures(), vec!["https://i0.hdslb.com/cover.jpg"]);
+    assert_eq!(result.url(), "https://t.bilibili.com/2");
+}
+
+#[test]
+fn test_render_rich_text() {
+    let json = r##"{
+        "id_str": "3",
+        "type": "DYNAMIC_TYPE_WORD",
+        "modules": {
+            "module_author": { "mid": 1, "name": "小熊猫", "pub_ts": 1700000200 },
+            "module_dynamic": {
+                "desc": {
+                    "text": "[doge]@UP #歌回# [小熊猫_呆]",
+                    "rich_text_nodes": [
+                        { "type": "RICH_TEXT_NODE_TYPE_EMOJI", "text": "[doge]", "emoji": { "icon_url": "https://i0.hdslb.com/doge.png" } },
+                        { "type": "RICH_TEXT_NODE_TYPE_AT", "text": "@UP", "rid": "2" },
+                        { "type": "RICH_TEXT_NODE_TYPE_TEXT", "text": " " },
+                        { "type": "RICH_TEXT_NODE_TYPE_TOPIC", "text": "#歌回#", "jump_url": "//search.bilibili.com/all?keyword=\"歌回\"&from=1" },
+                        { "type": "RICH_TEXT_NODE_TYPE_TEXT", "text": " " },
+                        { "type": "RICH_TEXT_NODE_TYPE_EMOJI", "text": "[小熊猫_呆]", "emoji": { "icon_url": "https://i0.hdslb.com/dai.png" } }
+                    ]
+                },
+                "major": null,
+                "additional": null
+            }
+        }
+    }"##;
+    let result = trans(serde_json::from_str(json).unwrap());
+
+    assert_eq!(
+        result.render(),
+        "🐶<a href=\"https://space.bilibili.com/2\">@UP</a> <a href=\"https://search.bilibili.com/all?keyword=&quot;歌回&quot;&amp;from=1\">#歌回#</a> <a href=\"https://i0.hdslb.com/dai.png\">[小熊猫_呆]</a>"
+    );
+    assert_eq!(result.plain_text(), "🐶@UP #歌回# [小熊猫_呆]");
+}
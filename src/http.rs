@@ -0,0 +1,66 @@
+use anyhow::Result;
+use redis::aio::MultiplexedConnection;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{error, info};
+
+use crate::{
+    checker, reservation,
+    subscription::{self, Kind},
+};
+
+pub async fn serve(addr: String, con: MultiplexedConnection) -> Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("HTTP server is listening on {}", addr);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let con = con.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, con).await {
+                error!("HTTP server has error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, con: MultiplexedConnection) -> Result<()> {
+    let mut buf = vec![0; 4096];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/reservations.ics") => {
+            let mut reservations = vec![];
+            for uid in subscription::list_ids(&con, Kind::BiliReservation).await? {
+                reservations.extend(checker::get_reservations(&con, uid).await?);
+            }
+            (
+                "200 OK",
+                "text/calendar; charset=utf-8",
+                reservation::to_ics(&reservations)?,
+            )
+        }
+        _ => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "Not Found".to_string(),
+        ),
+    };
+
+    let resp = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(resp.as_bytes()).await?;
+
+    Ok(())
+}
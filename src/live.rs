@@ -3,6 +3,9 @@ use dashmap::DashMap;
 use lazy_static::lazy_static;
 use reqwest::{header::HeaderMap, Client};
 use serde::Deserialize;
+use time::{format_description, macros::offset, PrimitiveDateTime};
+
+use crate::risk_control;
 
 #[derive(Debug, Deserialize)]
 struct LiveRoomInit {
@@ -39,14 +42,23 @@ struct LiveUserStatusData {
 
 #[derive(Debug, Deserialize)]
 struct LiveUserStatusDataInfo {
-    uid: u64,
     uname: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct LiveUserRoom {
+    data: LiveUserRoomData,
+}
+
+#[derive(Debug, Deserialize)]
+struct LiveUserRoomData {
+    #[serde(rename = "liveStatus")]
+    live_status: i32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LiveStatusResult {
     pub room_id: u64,
-    pub uid: u64,
     pub uname: String,
     pub title: String,
     pub live_status: i32,
@@ -54,6 +66,17 @@ pub struct LiveStatusResult {
     pub user_cover: String,
 }
 
+impl LiveStatusResult {
+    // live_time is in China Standard Time
+    pub fn live_timestamp(&self) -> Option<i64> {
+        let format =
+            format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]").ok()?;
+        let date = PrimitiveDateTime::parse(&self.live_time, &format).ok()?;
+
+        Some(date.assume_offset(offset!(+8)).unix_timestamp())
+    }
+}
+
 lazy_static! {
     static ref SHORT_ID_MAP: DashMap<String, u64> = DashMap::new();
 }
@@ -65,20 +88,17 @@ pub async fn get_live_status(room_id: u64, client: &Client) -> Result<LiveStatus
         "Referer",
         format!("https://live.bilibili.com/{}", room_id).parse()?,
     );
-    let live_room_status = client
-        .get(format!(
+    let live_room_status: LiveRoomStatus = risk_control::get_json(
+        client,
+        &format!(
             "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={}&from=room",
             room_id
-        ))
-        .headers(header_map)
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<LiveRoomStatus>()
-        .await?;
+        ),
+        header_map,
+    )
+    .await?;
     let live_room_data = live_room_status.data;
     let live_user_status = get_live_user_info(room_id, client).await?;
-    let uid = live_user_status.uid;
     let uname = live_user_status.uname;
     let title = live_room_data.title;
     let live_status = live_room_data.live_status;
@@ -87,7 +107,6 @@ pub async fn get_live_status(room_id: u64, client: &Client) -> Result<LiveStatus
 
     Ok(LiveStatusResult {
         room_id,
-        uid,
         uname,
         title,
         live_status,
@@ -96,6 +115,25 @@ pub async fn get_live_status(room_id: u64, client: &Client) -> Result<LiveStatus
     })
 }
 
+pub async fn is_user_live(uid: u64, client: &Client) -> Result<bool> {
+    let mut header_map = HeaderMap::new();
+    header_map.insert(
+        "Referer",
+        (format!("https://space.bilibili.com/{}", uid)).parse()?,
+    );
+    let r: LiveUserRoom = risk_control::get_json(
+        client,
+        &format!(
+            "https://api.live.bilibili.com/room/v1/Room/getRoomInfoOld?mid={}",
+            uid
+        ),
+        header_map,
+    )
+    .await?;
+
+    Ok(r.data.live_status == 1)
+}
+
 async fn get_room_id_from_short(room_id: u64, client: &Client) -> Result<u64> {
     let key = format!("short-id-{}", room_id);
     let room_id = if room_id < 10000 {
@@ -107,17 +145,15 @@ async fn get_room_id_from_short(room_id: u64, client: &Client) -> Result<u64> {
             "Referer",
             (format!("https://live.bilibili.com/{}", room_id)).parse()?,
         );
-        let r = client
-            .get(format!(
+        let r: LiveRoomInit = risk_control::get_json(
+            client,
+            &format!(
                 "https://api.live.bilibili.com/room/v1/Room/room_init?id={}",
                 room_id
-            ))
-            .headers(header_map)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<LiveRoomInit>()
-            .await?;
+            ),
+            header_map,
+        )
+        .await?;
         SHORT_ID_MAP.insert(key, r.data.room_id);
 
         r.data.room_id
@@ -134,20 +170,34 @@ async fn get_live_user_info(room_id: u64, client: &Client) -> Result<LiveUserSta
         "Referer",
         (format!("https://live.bilibili.com/{}", room_id)).parse()?,
     );
-    let resp = client
-        .get(format!(
+    let resp: LiveUserStatus = risk_control::get_json(
+        client,
+        &format!(
             "https://api.live.bilibili.com/live_user/v1/UserInfo/get_anchor_in_room?roomid={}",
             room_id
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<LiveUserStatus>()
-        .await?;
+        ),
+        header_map,
+    )
+    .await?;
 
     Ok(resp.data.info)
 }
 
+#[test]
+fn test_live_timestamp() {
+    let mut live = LiveStatusResult {
+        room_id: 22746343,
+        uname: "小熊猫".to_string(),
+        title: "晚安".to_string(),
+        live_status: 1,
+        live_time: "2022-05-01 20:00:00".to_string(),
+        user_cover: String::new(),
+    };
+    assert_eq!(live.live_timestamp(), Some(1651406400));
+    live.live_time = "0000-00-00 00:00:00".to_string();
+    assert_eq!(live.live_timestamp(), None);
+}
+
 #[tokio::test]
 async fn test() {
     let client = Client::new();
@@ -1,16 +1,34 @@
+use std::sync::Arc;
+
 use anyhow::{bail, Result};
 use futures::future::BoxFuture;
 use rand::Rng;
 use redis::aio::MultiplexedConnection;
+use reqwest::cookie::Jar;
+use risk_control::BiliError;
+use sender::TelegramSend;
+use subscription::{Kind, Route};
 use teloxide::prelude::*;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 use tracing::{error, info, warn};
-use weibo::WeiboClient;
+use weibo::{WeiboClient, WeiboError, WeiboLogin};
 
+mod article;
+mod bili_login;
 mod checker;
+mod command;
+mod comment;
 mod dynamic;
+mod http;
 mod live;
+mod outbox;
+mod reservation;
+mod risk_control;
 mod sender;
+mod snapshot;
+mod subscription;
+mod video;
+mod wbi;
 mod weibo;
 
 macro_rules! error_and_exit {
@@ -31,15 +49,22 @@ macro_rules! unwrap_or_exit {
     };
 }
 
+const BILI_LOGIN_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
 struct TaskArgs<'a> {
     con: &'a MultiplexedConnection,
     resp_client: reqwest::Client,
-    bot: Option<&'a AutoSend<Bot>>,
-    dynamic_id: Option<u64>,
-    live_id: Option<u64>,
-    telegram_chat_id: Option<i64>,
-    weibo: Option<&'a WeiboClient>,
-    weibo_profile_url: Option<String>,
+    bili_cookies: Arc<Jar>,
+    bili_login: bool,
+    bot: Option<&'a Bot>,
+    article_excerpt: usize,
+    reservation_remind_minutes: u64,
+    // default chats of the subscriptions without their own chats
+    chats: Vec<Route>,
+    admin_chat_id: Option<i64>,
+    weibo: &'a WeiboClient,
+    weibo_topic: Option<String>,
+    weibo_comment: bool,
 }
 
 #[tokio::main]
@@ -54,58 +79,135 @@ async fn main() {
 
     let (bot, chat_id) = init_tgbot();
 
-    let weibo_and_profile_url = unwrap_or_exit!(init_weibo_client().await);
+    let con = unwrap_or_exit!(init_redis().await);
+
+    let admin_chat_id = std::env::var("AILURUS_ADMIN_CHATID")
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok());
+    let admin_ids = unwrap_or_exit!(init_admin_ids(admin_chat_id));
 
-    let (weibo, profile_url) = weibo_and_profile_url;
+    unwrap_or_exit!(init_bilibili_targets(&con).await);
 
-    let (dynamic_id, live_id) = init_bilibili_dyn_and_live();
+    let weibo = unwrap_or_exit!(init_weibo_client(&con, bot.as_ref(), admin_chat_id).await);
 
-    if dynamic_id.is_none() && live_id.is_none() && weibo.is_none() {
+    // without admins, subscriptions can not be added by telegram commands
+    let can_subscribe = bot.is_some() && !admin_ids.is_empty();
+    if !can_subscribe && !unwrap_or_exit!(has_subscriptions(&con).await) {
         error_and_exit!(
             "Plaset set AILURUS_DYNAMIC to check dynamic \n
+            or set AILURUS_VIDEO to check video upload \n
+            or set AILURUS_RESERVATION to check live reservation \n
+            or set AILURUS_COMMENT to check comment \n
             or set AILURUS_LIVE to check live status \n
-            or set AILURUS_WEIBO_USERNAME and AILURUS_WEIBO_PASSWORD and AILURUS_PROFILE_URL to check weibo!"
+            or set AILURUS_PROFILE_URL to check weibo \n
+            or set AILURUS_ADMIN_CHATID or AILURUS_ADMIN_ID to subscribe with telegram commands!"
         );
     }
 
-    let con = unwrap_or_exit!(init_redis().await);
+    let (network_client, bili_cookies) = unwrap_or_exit!(init_network_client());
+
+    if let Err(e) = risk_control::refresh_buvid(&network_client, &bili_cookies).await {
+        warn!("Can not get bilibili buvid cookies: {}", e);
+    }
+
+    let bili_login = std::env::var("AILURUS_BILI_LOGIN").is_ok();
+
+    if bili_login {
+        if let Err(e) = bili_login::init(
+            &con,
+            &network_client,
+            &bili_cookies,
+            bot.as_ref(),
+            admin_chat_id,
+        )
+        .await
+        {
+            warn!("Can not login bilibili, continue without login: {}", e);
+        }
+    }
 
-    let network_client = unwrap_or_exit!(init_network_client());
+    if let Ok(addr) = std::env::var("AILURUS_HTTP_ADDR") {
+        let con = con.clone();
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(addr, con).await {
+                error!("HTTP server is stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(bot) = bot.as_ref().filter(|_| !admin_ids.is_empty()) {
+        tokio::spawn(command::serve(
+            bot.clone(),
+            con.clone(),
+            weibo.clone(),
+            admin_ids,
+        ));
+    }
 
     let task_args = TaskArgs {
         con: &con,
         resp_client: network_client,
+        bili_cookies,
+        bili_login,
         bot: bot.as_ref(),
-        dynamic_id,
-        live_id,
-        telegram_chat_id: chat_id.and_then(|x| x.parse::<i64>().ok()),
-        weibo: weibo.as_ref(),
-        weibo_profile_url: profile_url,
+        article_excerpt: std::env::var("AILURUS_ARTICLE_EXCERPT")
+            .ok()
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(300),
+        reservation_remind_minutes: std::env::var("AILURUS_RESERVATION_REMIND")
+            .ok()
+            .and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or(10),
+        chats: match chat_id {
+            Some(chat_id) => unwrap_or_exit!(subscription::parse_routes(&chat_id)),
+            None => vec![],
+        },
+        admin_chat_id,
+        weibo: &weibo,
+        weibo_topic: std::env::var("AILURUS_WEIBO_TOPIC").ok(),
+        weibo_comment: std::env::var("AILURUS_WEIBO_COMMENT").is_ok(),
     };
 
     tasker(task_args).await;
 }
 
-fn init_bilibili_dyn_and_live() -> (Option<u64>, Option<u64>) {
-    let mut args = vec![];
-    for i in &["AILURUS_DYNAMIC", "AILURUS_LIVE"] {
-        if let Ok(id) = std::env::var(i) {
-            if let Ok(id) = id.parse::<u64>() {
-                args.push(Some(id));
-            } else {
-                error_and_exit!(format!("var {} is not a number!", i));
+// subscriptions set by env are written into redis once, the same as telegram commands
+async fn init_bilibili_targets(con: &MultiplexedConnection) -> Result<()> {
+    for (var, kind) in &[
+        ("AILURUS_DYNAMIC", Kind::BiliDynamic),
+        ("AILURUS_VIDEO", Kind::BiliVideo),
+        ("AILURUS_RESERVATION", Kind::BiliReservation),
+        ("AILURUS_COMMENT", Kind::BiliComment),
+        ("AILURUS_LIVE", Kind::Live),
+    ] {
+        if let Ok(id) = std::env::var(var) {
+            if id.parse::<u64>().is_err() {
+                bail!("var {} is not a number!", var);
             }
-        } else {
-            args.push(None)
-        };
+            if subscription::import(con, *kind, &id).await? {
+                info!("Subscribed {} {} from env", kind, id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn has_subscriptions(con: &MultiplexedConnection) -> Result<bool> {
+    for kind in subscription::KINDS {
+        if !subscription::list(con, *kind).await?.is_empty() {
+            return Ok(true);
+        }
     }
-    let dynamic_id = args[0];
-    let live_id = args[1];
 
-    (dynamic_id, live_id)
+    Ok(false)
 }
 
-async fn init_weibo_client() -> Result<(Option<WeiboClient>, Option<String>)> {
+async fn init_weibo_client(
+    con: &MultiplexedConnection,
+    bot: Option<&Bot>,
+    admin_chat_id: Option<i64>,
+) -> Result<WeiboClient> {
     let account_and_password = if let Ok(account) = std::env::var("AILURUS_WEIBO_ACCOUNT") {
         if let Ok(password) = std::env::var("AILURUS_WEIBO_PASSWORD") {
             Some((account, password))
@@ -116,39 +218,66 @@ async fn init_weibo_client() -> Result<(Option<WeiboClient>, Option<String>)> {
         None
     };
 
-    let weibo = if let Some((account, password)) = account_and_password {
-        Some(login_weibo(&account, &password).await?)
+    let qrcode = std::env::var("AILURUS_WEIBO_QRCODE").is_ok();
+    let profile_url = std::env::var("AILURUS_PROFILE_URL").ok();
+
+    let login_method = if qrcode {
+        WeiboLogin::QrCode
+    } else if let Some((account, password)) = account_and_password {
+        WeiboLogin::Password { account, password }
     } else {
-        None
+        WeiboLogin::Visitor
     };
 
-    let profile_url = std::env::var("AILURUS_PROFILE_URL").ok();
+    let weibo = WeiboClient::new(login_method)?.with_admin(bot, admin_chat_id);
 
-    if weibo.is_none() && profile_url.is_some() {
-        bail!(
-            "AILURUS_PROFILE_URL is set but weibo account info not to set!\n
-        Please set AILURUS_WEIBO_USERNAME and AILURUS_WEIBO_PASSWORD!"
-        );
+    // login lazily if nobody is watched on weibo yet
+    if profile_url.is_none() && subscription::list(con, Kind::Weibo).await?.is_empty() {
+        return Ok(weibo);
     }
 
-    if weibo.is_some() && profile_url.is_none() {
-        bail!("Weibo account info is set but profile url not to set!\nPlease set AILURUS_PROFILE_URL!");
+    weibo.ensure_login(con).await?;
+
+    for target in profile_url
+        .iter()
+        .flat_map(|x| x.split(','))
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let uid = match weibo.resolve_uid(target).await {
+            Ok(uid) => uid,
+            Err(e) => {
+                error!("Can not resolve weibo target {}, skip it: {}", target, e);
+                continue;
+            }
+        };
+        info!("Weibo target {} is uid {}", target, uid);
+        subscription::import(con, Kind::Weibo, &uid).await?;
     }
 
-    Ok((weibo, profile_url))
+    Ok(weibo)
 }
 
-async fn login_weibo(account: &str, password: &str) -> Result<WeiboClient> {
-    let weibo = weibo::WeiboClient::new()?;
-    weibo.login(account, password).await?;
+// telegram users allowed to use the commands, the admin chat is the admin only when it is private
+fn init_admin_ids(admin_chat_id: Option<i64>) -> Result<Vec<u64>> {
+    let admin_ids = match std::env::var("AILURUS_ADMIN_ID") {
+        Ok(v) => v
+            .split(',')
+            .map(|x| x.trim().parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()?,
+        Err(_) => admin_chat_id
+            .and_then(|x| u64::try_from(x).ok())
+            .into_iter()
+            .collect(),
+    };
 
-    Ok(weibo)
+    Ok(admin_ids)
 }
 
-fn init_tgbot() -> (Option<AutoSend<Bot>>, Option<String>) {
+fn init_tgbot() -> (Option<Bot>, Option<String>) {
     let (bot, chat_id) = if std::env::var("TELOXIDE_TOKEN").is_ok() {
         if let Ok(v) = std::env::var("AILURUS_CHATID") {
-            (Some(Bot::from_env().auto_send()), Some(v))
+            (Some(Bot::from_env()), Some(v))
         } else {
             error_and_exit!("TELOXIDE_TOKEN is set but AILURUS_CHATID not to set!");
         }
@@ -175,65 +304,283 @@ async fn init_redis() -> Result<MultiplexedConnection> {
     Ok(connect)
 }
 
-fn init_network_client() -> Result<reqwest::Client> {
+fn init_network_client() -> Result<(reqwest::Client, Arc<Jar>)> {
+    let jar = Arc::new(Jar::default());
     let resp_client = reqwest::ClientBuilder::new()
         .user_agent("User-Agent: Mozilla/5.0 (X11; AOSC OS; Linux x86_64; rv:98.0) Gecko/20100101 Firefox/98.0")
+        .cookie_provider(jar.clone())
         .timeout(Duration::from_secs(30))
         .build()?;
 
-    Ok(resp_client)
+    Ok((resp_client, jar))
 }
 
 async fn tasker(task_args: TaskArgs<'_>) {
     let mut rng = rand::thread_rng();
+    let mut bili_login_checked_at = Instant::now();
 
     loop {
         let mut tasks = vec![];
 
+        if task_args.bili_login && bili_login_checked_at.elapsed() > BILI_LOGIN_CHECK_INTERVAL {
+            bili_login_checked_at = Instant::now();
+            if let Err(e) = bili_login::check_and_refresh(
+                task_args.con,
+                &task_args.resp_client,
+                &task_args.bili_cookies,
+                task_args.bot,
+                task_args.admin_chat_id,
+            )
+            .await
+            {
+                error!("Can not refresh bilibili cookies: {}", e);
+            }
+        }
+
         let sleep_time = rng.gen_range(60..=180);
 
-        if let Some(dyn_id) = task_args.dynamic_id {
+        for dyn_id in subscriptions(task_args.con, Kind::BiliDynamic).await {
+            let chats = routes(&task_args, Kind::BiliDynamic, &dyn_id.to_string()).await;
             let check_dynamic: BoxFuture<'_, Result<()>> = Box::pin(checker::check_dynamic_update(
                 task_args.con,
                 dyn_id,
                 &task_args.resp_client,
                 task_args.bot,
-                task_args.telegram_chat_id,
+                chats.clone(),
+                task_args.article_excerpt,
+            ));
+            tasks.push((format!("{} {}", Kind::BiliDynamic, dyn_id), check_dynamic));
+        }
+
+        for video_id in subscriptions(task_args.con, Kind::BiliVideo).await {
+            let chats = routes(&task_args, Kind::BiliVideo, &video_id.to_string()).await;
+            let check_video: BoxFuture<'_, Result<()>> = Box::pin(checker::check_video_update(
+                task_args.con,
+                video_id,
+                &task_args.resp_client,
+                task_args.bot,
+                chats.clone(),
+            ));
+            tasks.push((format!("{} {}", Kind::BiliVideo, video_id), check_video));
+        }
+
+        for reservation_id in subscriptions(task_args.con, Kind::BiliReservation).await {
+            let chats = routes(
+                &task_args,
+                Kind::BiliReservation,
+                &reservation_id.to_string(),
+            )
+            .await;
+            let check_reservation: BoxFuture<'_, Result<()>> =
+                Box::pin(checker::check_reservation(
+                    task_args.con,
+                    reservation_id,
+                    &task_args.resp_client,
+                    task_args.bot,
+                    chats.clone(),
+                    task_args.reservation_remind_minutes,
+                ));
+            tasks.push((
+                format!("{} {}", Kind::BiliReservation, reservation_id),
+                check_reservation,
+            ));
+        }
+
+        for comment_id in subscriptions(task_args.con, Kind::BiliComment).await {
+            let chats = routes(&task_args, Kind::BiliComment, &comment_id.to_string()).await;
+            let check_comment: BoxFuture<'_, Result<()>> = Box::pin(checker::check_comment(
+                task_args.con,
+                comment_id,
+                &task_args.resp_client,
+                task_args.bot,
+                chats.clone(),
+            ));
+            tasks.push((
+                format!("{} {}", Kind::BiliComment, comment_id),
+                check_comment,
             ));
-            tasks.push(check_dynamic);
         }
 
-        if let Some(live_id) = task_args.live_id {
+        for live_id in subscriptions(task_args.con, Kind::Live).await {
+            let chats = routes(&task_args, Kind::Live, &live_id.to_string()).await;
             let check_live: BoxFuture<'_, Result<()>> = Box::pin(checker::check_live_status(
                 task_args.con,
                 live_id,
                 &task_args.resp_client,
                 task_args.bot,
-                task_args.telegram_chat_id,
+                chats.clone(),
             ));
-            tasks.push(check_live);
+            tasks.push((format!("{} {}", Kind::Live, live_id), check_live));
         }
 
-        if let Some(weibo) = task_args.weibo {
+        let weibo_uids = match subscription::list(task_args.con, Kind::Weibo).await {
+            Ok(uids) => uids,
+            Err(e) => {
+                error!("Can not read weibo subscriptions: {}", e);
+                vec![]
+            }
+        };
+
+        for uid in weibo_uids {
+            let chats = routes(&task_args, Kind::Weibo, &uid).await;
             let check_weibo: BoxFuture<'_, Result<()>> = Box::pin(checker::check_weibo(
                 task_args.con,
                 task_args.bot,
-                weibo,
-                task_args.weibo_profile_url.as_ref().unwrap().clone(),
-                &task_args.resp_client,
-                task_args.telegram_chat_id,
+                task_args.weibo,
+                uid.clone(),
+                chats.clone(),
             ));
-            tasks.push(check_weibo);
+            tasks.push((format!("{} {}", Kind::Weibo, uid), check_weibo));
+
+            if let Some(topic) = &task_args.weibo_topic {
+                let check_weibo_topic: BoxFuture<'_, Result<()>> =
+                    Box::pin(checker::check_weibo_topic(
+                        task_args.con,
+                        task_args.bot,
+                        task_args.weibo,
+                        uid.clone(),
+                        topic.clone(),
+                        chats.clone(),
+                    ));
+                tasks.push((format!("{} {} 超话", Kind::Weibo, uid), check_weibo_topic));
+            }
+
+            if task_args.weibo_comment {
+                let check_weibo_comment: BoxFuture<'_, Result<()>> =
+                    Box::pin(checker::check_weibo_comment(
+                        task_args.con,
+                        task_args.bot,
+                        task_args.weibo,
+                        uid.clone(),
+                        chats.clone(),
+                    ));
+                tasks.push((format!("{} {} 评论", Kind::Weibo, uid), check_weibo_comment));
+            }
         }
 
+        let (labels, tasks): (Vec<_>, Vec<_>) = tasks.into_iter().unzip();
         let results = futures::future::join_all(tasks).await;
+        command::retain_status(&labels);
+        let mut weibo_renewed = false;
+        let mut blocked_hosts = vec![];
 
-        for i in results {
+        for (label, i) in labels.into_iter().zip(results) {
+            command::update_status(label, &i);
             if let Err(e) = i {
-                error!("{}", e);
+                if let Some(WeiboError::SessionExpired) = e.downcast_ref::<WeiboError>() {
+                    warn!("{}", e);
+                    // all weibo targets share one session, only login again once
+                    if !weibo_renewed {
+                        weibo_renewed = true;
+                        handle_weibo_expired(&task_args).await;
+                    }
+                    continue;
+                }
+                match e.downcast_ref::<BiliError>() {
+                    Some(BiliError::Cooling { .. }) => warn!("{}", e),
+                    Some(BiliError::RiskControl { host, strikes, .. }) => {
+                        error!("{}", e);
+                        // the tasks hitting the same host share one strike
+                        if !blocked_hosts.contains(host) {
+                            blocked_hosts.push(host.clone());
+                            handle_risk_control(&task_args, host, *strikes).await;
+                        }
+                    }
+                    None => error!("{}", e),
+                }
+            }
+        }
+
+        // also retries the notifications left by the former runs
+        if let Some(bot) = task_args.bot {
+            if let Err(e) = outbox::deliver(task_args.con, bot, &task_args.resp_client).await {
+                error!("Can not deliver outbox: {}", e);
             }
         }
 
         sleep(Duration::from_secs(sleep_time)).await;
     }
 }
+
+async fn routes(task_args: &TaskArgs<'_>, kind: Kind, id: &str) -> Vec<Route> {
+    match subscription::routes(task_args.con, kind, id, &task_args.chats).await {
+        Ok(routes) => routes,
+        Err(e) => {
+            error!("Can not read chats of {} {}: {}", kind, id, e);
+            task_args.chats.clone()
+        }
+    }
+}
+
+async fn subscriptions(con: &MultiplexedConnection, kind: Kind) -> Vec<u64> {
+    match subscription::list_ids(con, kind).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Can not read {} subscriptions: {}", kind, e);
+            vec![]
+        }
+    }
+}
+
+async fn handle_weibo_expired(task_args: &TaskArgs<'_>) {
+    let e = match task_args.weibo.ensure_login(task_args.con).await {
+        Ok(()) => {
+            info!("Weibo session is renewed");
+            return;
+        }
+        Err(e) => e,
+    };
+    error!("Can not renew weibo session: {}", e);
+
+    if let (Some(bot), Some(chat_id)) = (task_args.bot, task_args.admin_chat_id) {
+        let mut telegram_sends = vec![TelegramSend {
+            msg: format!(
+                "<b>微博登录已失效，重新登录失败！</b>\n{}",
+                sender::escape_html(&e.to_string())
+            ),
+            ..Default::default()
+        }];
+        if let Err(e) = sender::send(
+            &mut telegram_sends,
+            bot,
+            &Route::new(chat_id),
+            &task_args.resp_client,
+        )
+        .await
+        {
+            error!("Can not notify admin: {}", e);
+        }
+    }
+}
+
+async fn handle_risk_control(task_args: &TaskArgs<'_>, host: &str, strikes: u32) {
+    if let Err(e) =
+        risk_control::refresh_buvid(&task_args.resp_client, &task_args.bili_cookies).await
+    {
+        warn!("Can not refresh bilibili buvid cookies: {}", e);
+    }
+
+    if strikes != risk_control::NOTIFY_ADMIN_STRIKES {
+        return;
+    }
+
+    if let (Some(bot), Some(chat_id)) = (task_args.bot, task_args.admin_chat_id) {
+        let mut telegram_sends = vec![TelegramSend {
+            msg: format!(
+                "<b>{} 已经连续 {} 次触发 B 站风控！</b>\n请检查服务器 IP 或者登录状态。",
+                host, strikes
+            ),
+            ..Default::default()
+        }];
+        if let Err(e) = sender::send(
+            &mut telegram_sends,
+            bot,
+            &Route::new(chat_id),
+            &task_args.resp_client,
+        )
+        .await
+        {
+            error!("Can not notify admin: {}", e);
+        }
+    }
+}
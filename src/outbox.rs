@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use teloxide::Bot;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+
+use crate::{
+    sender::{self, TelegramSend},
+    snapshot::{self, Snapshot},
+    subscription::Route,
+};
+
+const OUTBOX_KEY: &str = "outbox";
+const OUTBOX_SEQ_KEY: &str = "outbox-seq";
+const OUTBOX_DONE_KEY: &str = "outbox-done";
+// events that can not be read any more, kept for a look by hand
+const OUTBOX_DEAD_KEY: &str = "outbox-dead";
+// delivered events are remembered for a while, so detecting them again does not send twice
+const OUTBOX_DONE_TTL: i64 = 24 * 60 * 60;
+// give up the chats still failing after this
+const OUTBOX_EXPIRE: i64 = 3 * 24 * 60 * 60;
+// the message ids are only needed for a while, e.g. until the live ends
+const MESSAGE_IDS_TTL: usize = 7 * 24 * 60 * 60;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Event {
+    id: String,
+    seq: u64,
+    send: TelegramSend,
+    chats: Vec<String>,
+    delivered: Vec<String>,
+    // saved with the message ids once delivered
+    snapshot: Option<(String, Snapshot)>,
+    // the message id of each chat is saved to this hash once delivered to the chat
+    #[serde(default)]
+    message_ids_key: Option<String>,
+    created_at: i64,
+}
+
+// the id names the source, the item and the action, like `bili_dynamic:<uid>:<dynamic_id>:new`,
+// so detecting the same thing again does not send it twice
+pub async fn push(
+    con: &MultiplexedConnection,
+    id: String,
+    send: TelegramSend,
+    chats: &[Route],
+    snapshot: Option<(String, Snapshot)>,
+    message_ids_key: Option<String>,
+) -> Result<()> {
+    let mut con = con.clone();
+
+    let done: Option<i64> = con.zscore(OUTBOX_DONE_KEY, &id).await?;
+    if done.is_some() {
+        info!("Event {} is already delivered, skip", id);
+        return Ok(());
+    }
+
+    let existing: Option<String> = con.hget(OUTBOX_KEY, &id).await?;
+    let event = match existing.and_then(|x| serde_json::from_str::<Event>(&x).ok()) {
+        // still waiting, only the chats it does not have yet are added
+        Some(mut event) => {
+            let targets = event
+                .chats
+                .iter()
+                .filter_map(|x| x.parse::<Route>().ok())
+                .map(|x| x.target())
+                .collect::<Vec<_>>();
+            let new_chats = chats
+                .iter()
+                .filter(|x| !targets.contains(&x.target()))
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            if new_chats.is_empty() {
+                info!("Event {} is already in outbox, skip", id);
+                return Ok(());
+            }
+            info!(
+                "Event {} is already in outbox, adding chats {:?}",
+                id, new_chats
+            );
+            event.chats.extend(new_chats);
+            event
+        }
+        None => Event {
+            id: id.clone(),
+            seq: con.incr(OUTBOX_SEQ_KEY, 1).await?,
+            send,
+            chats: chats.iter().map(|x| x.to_string()).collect(),
+            delivered: vec![],
+            snapshot,
+            message_ids_key,
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        },
+    };
+    con.hset::<_, _, _, ()>(OUTBOX_KEY, &id, serde_json::to_string(&event)?)
+        .await?;
+
+    Ok(())
+}
+
+// drops the event still waiting, the chats already delivered are left as they are
+pub async fn cancel(con: &MultiplexedConnection, id: &str) -> Result<()> {
+    let mut con = con.clone();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    let (removed,): (bool,) = redis::pipe()
+        .atomic()
+        .hdel(OUTBOX_KEY, id)
+        .zadd(OUTBOX_DONE_KEY, id, now)
+        .ignore()
+        .query_async(&mut con)
+        .await?;
+    if removed {
+        info!("Event {} is cancelled", id);
+    }
+
+    Ok(())
+}
+
+pub async fn deliver(con: &MultiplexedConnection, bot: &Bot, client: &Client) -> Result<()> {
+    let mut con = con.clone();
+    let saved: HashMap<String, String> = con.hgetall(OUTBOX_KEY).await?;
+    let mut events = vec![];
+    // a broken entry must not stop the others
+    for (id, v) in saved {
+        match serde_json::from_str::<Event>(&v) {
+            Ok(event) => events.push(event),
+            Err(e) => {
+                error!(
+                    "Event {} can not be read, moving it to {}: {}",
+                    id, OUTBOX_DEAD_KEY, e
+                );
+                redis::pipe()
+                    .atomic()
+                    .hset(OUTBOX_DEAD_KEY, &id, v)
+                    .ignore()
+                    .hdel(OUTBOX_KEY, &id)
+                    .ignore()
+                    .query_async::<_, ()>(&mut con)
+                    .await?;
+            }
+        }
+    }
+    events.sort_unstable_by_key(|x| x.seq);
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    // keeps the order in a chat, the later events wait for the failed one
+    let mut blocked = HashSet::new();
+
+    for mut event in events {
+        let mut routes = vec![];
+        let mut broken = vec![];
+        for chat in &event.chats {
+            match chat.parse::<Route>() {
+                Ok(route) => routes.push((chat.clone(), route)),
+                Err(e) => {
+                    error!("Event {} has unknown chat {}: {}", event.id, chat, e);
+                    broken.push(chat.clone());
+                }
+            }
+        }
+        // the unknown chats are moved away, the others are still delivered
+        if !broken.is_empty() {
+            let dead = Event {
+                chats: broken.clone(),
+                ..event.clone()
+            };
+            event.chats.retain(|x| !broken.contains(x));
+            redis::pipe()
+                .atomic()
+                .hset(OUTBOX_DEAD_KEY, &event.id, serde_json::to_string(&dead)?)
+                .ignore()
+                .hset(OUTBOX_KEY, &event.id, serde_json::to_string(&event)?)
+                .ignore()
+                .query_async::<_, ()>(&mut con)
+                .await?;
+        }
+
+        for (chat, route) in routes {
+            let target = route.target();
+            if event.delivered.contains(&chat) || blocked.contains(&target) {
+                continue;
+            }
+
+            let mut telegram_sends = [event.send.clone()];
+            let result = sender::send(&mut telegram_sends, bot, &route, client).await;
+            // keeps what is already sent to the chat, the retry goes on from there
+            let [send] = telegram_sends;
+            event.send = send;
+
+            match result {
+                Ok(()) => {
+                    if let (Some(key), Some(message_id)) =
+                        (&event.message_ids_key, event.send.message_ids.get(&target))
+                    {
+                        redis::pipe()
+                            .atomic()
+                            .hset(key, &target, *message_id)
+                            .ignore()
+                            .expire(key, MESSAGE_IDS_TTL)
+                            .ignore()
+                            .query_async::<_, ()>(&mut con)
+                            .await?;
+                    }
+                    event.delivered.push(chat);
+                }
+                Err(e) => {
+                    error!("Can not deliver event {} to {}: {}", event.id, target, e);
+                    blocked.insert(target);
+                }
+            }
+            // acknowledge right away, a restart will not send it again
+            con.hset::<_, _, _, ()>(OUTBOX_KEY, &event.id, serde_json::to_string(&event)?)
+                .await?;
+        }
+
+        if event.delivered.len() == event.chats.len() {
+            finish(&con, event, now).await?;
+        } else if now - event.created_at > OUTBOX_EXPIRE {
+            error!(
+                "Event {} can not be delivered to all chats, giving up: {}",
+                event.id, event.send.msg
+            );
+            finish(&con, event, now).await?;
+        } else {
+            warn!("Event {} is not delivered yet, retry later", event.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn finish(con: &MultiplexedConnection, event: Event, now: i64) -> Result<()> {
+    let mut con = con.clone();
+
+    if let Some((key, mut snapshot)) = event.snapshot {
+        // an edited post keeps replying to its first notification
+        for (target, message_id) in event.send.message_ids {
+            snapshot.message_ids.entry(target).or_insert(message_id);
+        }
+        snapshot::save(&con, &key, &snapshot).await?;
+    }
+
+    redis::pipe()
+        .atomic()
+        .hdel(OUTBOX_KEY, &event.id)
+        .ignore()
+        .zadd(OUTBOX_DONE_KEY, &event.id, now)
+        .ignore()
+        .zrembyscore(OUTBOX_DONE_KEY, 0, now - OUTBOX_DONE_TTL)
+        .ignore()
+        .query_async::<_, ()>(&mut con)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use anyhow::Result;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use reqwest::{header::HeaderMap, Client};
+use serde::{Deserialize, Serialize};
+use time::{format_description, OffsetDateTime};
+
+use crate::risk_control;
+
+const LIVE_RESERVATION_TYPE: i64 = 2;
+const RESERVATION_ACTIVE_STATE: i64 = 100;
+// the states below this (-100, -300 ...) are cancelled by the user or the platform
+const RESERVATION_CANCELLED_STATE: i64 = 0;
+const ICS_EVENT_HOURS: i64 = 2;
+
+#[derive(Debug, Deserialize)]
+struct SpaceReservation {
+    data: Option<Vec<SpaceReservationItem>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpaceReservationItem {
+    sid: u64,
+    name: String,
+    #[serde(rename = "type")]
+    reservation_type: i64,
+    state: i64,
+    live_plan_start_time: Option<u64>,
+    stime: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserCard {
+    data: UserCardData,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserCardData {
+    card: UserCardDataCard,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserCardDataCard {
+    name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub sid: u64,
+    pub uid: u64,
+    pub title: String,
+    pub start_time: u64,
+    #[serde(default)]
+    pub reminded: bool,
+    // counts the edits, tells the notifications of the same edit apart
+    #[serde(default)]
+    pub revision: u32,
+}
+
+impl Reservation {
+    pub fn url(&self) -> String {
+        format!("https://space.bilibili.com/{}", self.uid)
+    }
+}
+
+pub struct LiveReservations {
+    pub active: Vec<Reservation>,
+    // the sids of the reservations marked as cancelled
+    pub cancelled: Vec<u64>,
+}
+
+lazy_static! {
+    static ref USER_NAME_MAP: DashMap<u64, String> = DashMap::new();
+}
+
+pub async fn get_live_reservations(uid: u64, client: &Client) -> Result<LiveReservations> {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        "Referer",
+        (format!("https://space.bilibili.com/{}", uid)).parse()?,
+    );
+    let r: SpaceReservation = risk_control::get_json(
+        client,
+        &format!("https://api.bilibili.com/x/space/reservation?vmid={}", uid),
+        headers,
+    )
+    .await?;
+
+    let (active, others): (Vec<_>, Vec<_>) = r
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|x| x.reservation_type == LIVE_RESERVATION_TYPE)
+        .partition(|x| x.state == RESERVATION_ACTIVE_STATE);
+
+    let active = active
+        .into_iter()
+        .map(|x| Reservation {
+            sid: x.sid,
+            uid,
+            title: x.name,
+            start_time: x.live_plan_start_time.unwrap_or(x.stime),
+            reminded: false,
+            revision: 0,
+        })
+        .collect();
+    let cancelled = others
+        .iter()
+        .filter(|x| x.state < RESERVATION_CANCELLED_STATE)
+        .map(|x| x.sid)
+        .collect();
+
+    Ok(LiveReservations { active, cancelled })
+}
+
+pub async fn get_user_name(uid: u64, client: &Client) -> Result<String> {
+    if let Some(name) = USER_NAME_MAP.get(&uid) {
+        return Ok(name.clone());
+    }
+
+    let card: UserCard = risk_control::get_json(
+        client,
+        &format!("https://api.bilibili.com/x/web-interface/card?mid={}", uid),
+        HeaderMap::new(),
+    )
+    .await?;
+    USER_NAME_MAP.insert(uid, card.data.card.name.clone());
+
+    Ok(card.data.card.name)
+}
+
+pub fn to_ics(reservations: &[Reservation]) -> Result<String> {
+    let format = format_description::parse("[year][month][day]T[hour][minute][second]Z")?;
+    let now = OffsetDateTime::now_utc().format(&format)?;
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//ailurus-spy//live reservation//ZH".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:直播预约".to_string(),
+    ];
+
+    for i in reservations {
+        let start = OffsetDateTime::from_unix_timestamp(i.start_time.try_into()?)?;
+        let end = start + time::Duration::hours(ICS_EVENT_HOURS);
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@ailurus-spy", i.sid),
+            format!("DTSTAMP:{}", now),
+            format!("DTSTART:{}", start.format(&format)?),
+            format!("DTEND:{}", end.format(&format)?),
+            format!("SUMMARY:{}", ics_escape(&i.title)),
+            format!("URL:{}", i.url()),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    Ok(lines
+        .iter()
+        .map(|x| ics_fold(x))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+        + "\r\n")
+}
+
+fn ics_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+fn ics_fold(line: &str) -> String {
+    let mut result = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            result.push_str("\r\n ");
+            len = 1;
+        }
+        result.push(c);
+        len += c.len_utf8();
+    }
+
+    result
+}
+
+#[tokio::test]
+async fn test() {
+    let client = Client::new();
+    let reservations = get_live_reservations(1501380958, &client).await.unwrap();
+    dbg!(reservations.active, reservations.cancelled);
+}
+
+#[test]
+fn test_to_ics() {
+    let ics = to_ics(&[Reservation {
+        sid: 1,
+        uid: 1501380958,
+        title: "直播预约：小熊猫的歌回, 不见不散".to_string(),
+        start_time: 1700000000,
+        reminded: false,
+        revision: 0,
+    }])
+    .unwrap();
+
+    assert!(ics.contains("UID:1@ailurus-spy\r\n"));
+    assert!(ics.contains("DTSTART:20231114T221320Z\r\n"));
+    assert!(ics.contains("DTEND:20231115T001320Z\r\n"));
+    assert!(ics.contains("SUMMARY:直播预约：小熊猫的歌回\\, 不见不散\r\n"));
+    assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    assert!(ics.split("\r\n").all(|x| x.len() <= 75));
+}
@@ -0,0 +1,218 @@
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use reqwest::{cookie::Jar, header::HeaderMap, Client, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::info;
+
+const SPI_URL: &str = "https://api.bilibili.com/x/frontend/finger/spi";
+const COOKIE_URL: &str = "https://www.bilibili.com";
+const RISK_CONTROL_CODES: &[i64] = &[-352, -412];
+const BASE_COOLING_SECS: u64 = 10 * 60;
+const MAX_COOLING_SECS: u64 = 2 * 60 * 60;
+pub const NOTIFY_ADMIN_STRIKES: u32 = 3;
+
+#[derive(Debug)]
+pub enum BiliError {
+    RiskControl {
+        host: String,
+        code: i64,
+        strikes: u32,
+    },
+    Cooling {
+        host: String,
+        remaining: Duration,
+    },
+}
+
+impl fmt::Display for BiliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BiliError::RiskControl {
+                host,
+                code,
+                strikes,
+            } => write!(
+                f,
+                "{} is blocked by bilibili risk control (code {}, {} strikes)",
+                host, code, strikes
+            ),
+            BiliError::Cooling { host, remaining } => write!(
+                f,
+                "{} is cooling down after risk control, {}s remaining",
+                host,
+                remaining.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BiliError {}
+
+#[derive(Debug, Deserialize)]
+struct BiliCode {
+    code: i64,
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Spi {
+    data: SpiData,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpiData {
+    b_3: String,
+    b_4: String,
+}
+
+struct Cooling {
+    until: Instant,
+    strikes: u32,
+}
+
+lazy_static! {
+    static ref COOLING_MAP: DashMap<String, Cooling> = DashMap::new();
+}
+
+pub async fn get_json<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    headers: HeaderMap,
+) -> Result<T> {
+    let (code, json) = get_json_with_code(client, url, headers).await?;
+
+    if code.code != 0 {
+        bail!(
+            "{} returned code {}: {}",
+            url,
+            code.code,
+            code.message.unwrap_or_default()
+        );
+    }
+
+    Ok(serde_json::from_value(json)?)
+}
+
+pub async fn get_code(client: &Client, url: &str, headers: HeaderMap) -> Result<i64> {
+    let (code, _) = get_json_with_code(client, url, headers).await?;
+
+    Ok(code.code)
+}
+
+async fn get_json_with_code(
+    client: &Client,
+    url: &str,
+    headers: HeaderMap,
+) -> Result<(BiliCode, serde_json::Value)> {
+    let host = Url::parse(url)?
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host!", url))?
+        .to_string();
+
+    if let Some(remaining) = cooling_remaining(&host) {
+        return Err(BiliError::Cooling { host, remaining }.into());
+    }
+
+    let resp = client.get(url).headers(headers).send().await?;
+
+    if resp.status() == StatusCode::PRECONDITION_FAILED {
+        return Err(risk_control(host, 412).into());
+    }
+
+    let json = resp.error_for_status()?.json::<serde_json::Value>().await?;
+    let code = BiliCode::deserialize(&json)?;
+
+    if RISK_CONTROL_CODES.contains(&code.code) {
+        return Err(risk_control(host, code.code).into());
+    }
+
+    if COOLING_MAP.remove(&host).is_some() {
+        info!("{} is recovered from risk control", host);
+    }
+
+    Ok((code, json))
+}
+
+fn cooling_remaining(host: &str) -> Option<Duration> {
+    COOLING_MAP
+        .get(host)
+        .and_then(|x| x.until.checked_duration_since(Instant::now()))
+}
+
+// pauses the host, the requests sent before the cooling started do not count again
+fn risk_control(host: String, code: i64) -> BiliError {
+    let now = Instant::now();
+    let strikes = {
+        let mut entry = COOLING_MAP.entry(host.clone()).or_insert(Cooling {
+            until: now,
+            strikes: 0,
+        });
+        if entry.until <= now {
+            entry.strikes += 1;
+            entry.until = now + cooling_duration(entry.strikes);
+        }
+
+        entry.strikes
+    };
+
+    BiliError::RiskControl {
+        host,
+        code,
+        strikes,
+    }
+}
+
+fn cooling_duration(strikes: u32) -> Duration {
+    let secs = BASE_COOLING_SECS.saturating_mul(1 << strikes.saturating_sub(1).min(16));
+
+    Duration::from_secs(secs.min(MAX_COOLING_SECS))
+}
+
+pub async fn refresh_buvid(client: &Client, jar: &Arc<Jar>) -> Result<()> {
+    let spi = client
+        .get(SPI_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Spi>()
+        .await?;
+
+    let url = Url::parse(COOKIE_URL)?;
+    jar.add_cookie_str(
+        &format!("buvid3={}; Domain=.bilibili.com; Path=/", spi.data.b_3),
+        &url,
+    );
+    jar.add_cookie_str(
+        &format!("buvid4={}; Domain=.bilibili.com; Path=/", spi.data.b_4),
+        &url,
+    );
+    info!("Refreshed bilibili buvid cookies");
+
+    Ok(())
+}
+
+#[test]
+fn test_cooling_duration() {
+    assert_eq!(cooling_duration(1), Duration::from_secs(600));
+    assert_eq!(cooling_duration(2), Duration::from_secs(1200));
+    assert_eq!(cooling_duration(100), Duration::from_secs(MAX_COOLING_SECS));
+
+    let host = "test.bilibili.com";
+    assert!(matches!(
+        risk_control(host.to_string(), -352),
+        BiliError::RiskControl { strikes: 1, .. }
+    ));
+    // still cooling, the same strike
+    assert!(matches!(
+        risk_control(host.to_string(), -412),
+        BiliError::RiskControl { strikes: 1, .. }
+    ));
+    assert!(cooling_remaining(host).is_some());
+}
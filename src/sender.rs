@@ -1,155 +1,968 @@
-use std::io::Cursor;
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 
-use anyhow::Result;
-use image::io::Reader as ImageReader;
+use anyhow::{anyhow, bail, Result};
+use dashmap::DashMap;
+use image::{io::Reader as ImageReader, GrayImage, Luma};
+use lazy_static::lazy_static;
+use qrcode::{render::unicode, Color, QrCode};
 use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
 use teloxide::{
-    adaptors::AutoSend,
-    payloads::{SendMessageSetters, SendPhotoSetters},
+    payloads::{
+        EditMessageCaptionSetters, EditMessageTextSetters, GetUpdatesSetters, SendAnimationSetters,
+        SendDocumentSetters, SendMediaGroupSetters, SendMessageSetters, SendPhotoSetters,
+        SendVideoSetters,
+    },
     prelude::Requester,
-    types::{ChatId, InputFile, InputMedia, InputMediaPhoto, ParseMode, Recipient},
-    Bot,
+    requests::{Output, Request},
+    types::{
+        ChatId, InputFile, InputMedia, InputMediaDocument, InputMediaPhoto, InputMediaVideo,
+        Message, MessageId, ParseMode, Recipient, UpdateKind,
+    },
+    ApiError, Bot, RequestError,
+};
+use tokio::{
+    sync::oneshot,
+    time::{sleep, sleep_until, Instant},
 };
-use tracing::warn;
+use tracing::{error, warn};
+
+use crate::subscription::Route;
+
+const MAX_UPLOAD_SIZE: usize = 50 * 1024 * 1024;
+const CAPTION_LIMIT: usize = 1024;
+const MESSAGE_LIMIT: usize = 4096;
+const ALBUM_LIMIT: usize = 10;
+// telegram allows about 1 message per second in a chat, 20 per minute in a group
+// and 30 per second for a bot
+const CHAT_SEND_INTERVAL: Duration = Duration::from_secs(1);
+const GROUP_SEND_INTERVAL: Duration = Duration::from_secs(3);
+const GLOBAL_SEND_INTERVAL: Duration = Duration::from_millis(35);
+const SEND_RETRY: u32 = 3;
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+enum Media {
+    Photo(String),
+    Video { url: String, cover: Option<String> },
+    Document(String),
+}
+
+impl TelegramSend {
+    // photos and videos can be mixed in an album, documents can only be with documents
+    fn albums(&self) -> Vec<Vec<Media>> {
+        let mut visual = vec![];
+        match (&self.video, &self.photo) {
+            (Some(url), cover) => visual.push(Media::Video {
+                url: url.clone(),
+                cover: cover.clone(),
+            }),
+            (None, Some(photo)) => visual.push(Media::Photo(photo.clone())),
+            (None, None) => (),
+        }
+        for i in self.photos.iter().flatten() {
+            visual.push(Media::Photo(i.clone()));
+        }
+        let documents = self
+            .documents
+            .iter()
+            .flatten()
+            .map(|x| Media::Document(x.clone()))
+            .collect::<Vec<_>>();
+
+        let mut albums = vec![];
+        for mut media in [visual, documents] {
+            for size in album_sizes(media.len()) {
+                let rest = media.split_off(size);
+                albums.push(media);
+                media = rest;
+            }
+        }
+
+        albums
+    }
+}
+
+// albums are as even as possible, since a media group can not have only one item
+fn album_sizes(n: usize) -> Vec<usize> {
+    let count = n.div_ceil(ALBUM_LIMIT);
+    (0..count)
+        .map(|i| n / count + usize::from(i < n % count))
+        .collect()
+}
 
+// once the command dispatcher owns getUpdates, admin replies are handed over by it
+static DISPATCHING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref ADMIN_REPLY: Mutex<Option<oneshot::Sender<String>>> = Mutex::new(None);
+    static ref CHAT_NEXT_SEND: DashMap<i64, Instant> = DashMap::new();
+    static ref GLOBAL_NEXT_SEND: Mutex<Instant> = Mutex::new(Instant::now());
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct TelegramSend {
     pub msg: String,
     pub photos: Option<Vec<String>>,
     pub photo: Option<String>,
+    pub video: Option<String>,
+    pub animations: Option<Vec<String>>,
+    pub documents: Option<Vec<String>>,
+    // route target -> message id, one for each chat the message is sent to
+    pub reply_to: HashMap<String, i32>,
+    pub message_ids: HashMap<String, i32>,
+    // route target -> albums already sent, a retry goes on from the failed one
+    #[serde(default)]
+    pub albums_sent: HashMap<String, usize>,
 }
 
-macro_rules! send_msg {
-    ($bot:ident, $chat_id:ident, $msg:expr) => {
-        $bot.send_message(Recipient::Id(ChatId($chat_id)), &$msg)
-            .parse_mode(ParseMode::Html)
-            .await?;
+// every request is sent to the topic of the route, silently if asked,
+// and waits for its turn in the queue of the chat
+macro_rules! route {
+    ($req:expr, $route:ident) => {
+        route!($req, $route, 1)
     };
+    ($req:expr, $route:ident, $weight:expr) => {{
+        let mut req = $req.disable_notification($route.silent);
+        if let Some(thread_id) = $route.thread_id {
+            req = req.message_thread_id(thread_id);
+        }
+        queue($route.chat_id, $weight, req)
+    }};
+}
+
+macro_rules! send_msg {
+    ($bot:ident, $route:ident, $msg:expr, $reply_to:expr) => {{
+        let mut req = $bot
+            .send_message(Recipient::Id(ChatId($route.chat_id)), &$msg)
+            .parse_mode(ParseMode::Html);
+        if let Some(reply_to) = $reply_to {
+            req = req
+                .reply_to_message_id(MessageId(reply_to))
+                .allow_sending_without_reply(true);
+        }
+        route!(req, $route).await
+    }};
 }
 
 macro_rules! send_photo {
-    ($bot:ident, $chat_id:ident, $photo:ident, $msg:expr) => {
-        $bot.send_photo(
-            Recipient::Id(ChatId($chat_id)),
-            InputFile::url(Url::parse($photo)?),
+    ($bot:ident, $route:ident, $photo:ident, $msg:expr) => {
+        route!(
+            $bot.send_photo(
+                Recipient::Id(ChatId($route.chat_id)),
+                InputFile::url(Url::parse($photo)?),
+            )
+            .caption(&$msg)
+            .parse_mode(ParseMode::Html),
+            $route
         )
-        .caption(&$msg)
-        .parse_mode(ParseMode::Html)
         .await
     };
 }
 
 macro_rules! send_photo_with_bytes {
-    ($bot:ident, $chat_id:ident, $photo:ident, $msg:expr) => {
-        $bot.send_photo(Recipient::Id(ChatId($chat_id)), InputFile::memory($photo))
+    ($bot:ident, $route:ident, $photo:ident, $msg:expr) => {
+        route!(
+            $bot.send_photo(
+                Recipient::Id(ChatId($route.chat_id)),
+                InputFile::memory($photo)
+            )
+            .caption($msg)
+            .parse_mode(ParseMode::Html),
+            $route
+        )
+        .await
+    };
+}
+
+macro_rules! send_video_with_bytes {
+    ($bot:ident, $route:ident, $video:ident, $msg:expr) => {
+        route!(
+            $bot.send_video(
+                Recipient::Id(ChatId($route.chat_id)),
+                InputFile::memory($video).file_name("video.mp4"),
+            )
             .caption($msg)
             .parse_mode(ParseMode::Html)
-            .await
+            .supports_streaming(true),
+            $route
+        )
+        .await
+    };
+}
+
+macro_rules! send_animation_with_bytes {
+    ($bot:ident, $route:ident, $animation:ident) => {
+        route!(
+            $bot.send_animation(
+                Recipient::Id(ChatId($route.chat_id)),
+                InputFile::memory($animation).file_name("animation.mp4"),
+            ),
+            $route
+        )
+        .await
+    };
+}
+
+macro_rules! send_document {
+    ($bot:ident, $route:ident, $document:expr, $msg:expr) => {
+        route!(
+            $bot.send_document(Recipient::Id(ChatId($route.chat_id)), $document)
+                .caption($msg)
+                .parse_mode(ParseMode::Html),
+            $route
+        )
+        .await
     };
 }
 
 macro_rules! send_group {
-    ($bot:ident, $chat_id:ident, $groups:ident) => {
-        $bot.send_media_group(Recipient::Id(ChatId($chat_id)), $groups)
-            .await
+    ($bot:ident, $route:ident, $groups:ident) => {{
+        // every item of an album counts in the flood limit
+        let weight = $groups.len() as u32;
+        route!(
+            $bot.send_media_group(Recipient::Id(ChatId($route.chat_id)), $groups),
+            $route,
+            weight
+        )
+        .await
+    }};
+}
+
+// reserves the next free slot of both the chat and the bot, returns when to send
+fn reserve(chat_id: i64, weight: u32) -> Instant {
+    let now = Instant::now();
+    // groups and channels have a lower limit than private chats
+    let interval = if chat_id < 0 {
+        GROUP_SEND_INTERVAL
+    } else {
+        CHAT_SEND_INTERVAL
     };
+
+    let mut next = CHAT_NEXT_SEND.entry(chat_id).or_insert(now);
+    let mut global = GLOBAL_NEXT_SEND.lock().unwrap();
+    let at = (*next).max(*global).max(now);
+    *next = at + interval * weight;
+    *global = at + GLOBAL_SEND_INTERVAL * weight;
+
+    at
+}
+
+// pushes the queue of the chat back when telegram asks to wait
+fn delay(chat_id: i64, retry_after: Duration) {
+    let at = Instant::now() + retry_after;
+    let mut next = CHAT_NEXT_SEND.entry(chat_id).or_insert(at);
+    *next = (*next).max(at);
+}
+
+async fn queue<R>(chat_id: i64, weight: u32, req: R) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+{
+    let mut retries = 0;
+    loop {
+        sleep_until(reserve(chat_id, weight)).await;
+        match req.send_ref().await {
+            Err(RequestError::RetryAfter(retry_after)) => {
+                warn!(
+                    "Telegram flood control of chat {}, retry after {:?}",
+                    chat_id, retry_after
+                );
+                delay(chat_id, retry_after);
+            }
+            Err(RequestError::Network(e)) if retries < SEND_RETRY => {
+                retries += 1;
+                warn!("Telegram request has network error: {}, retrying ...", e);
+                sleep(SEND_RETRY_DELAY * retries).await;
+            }
+            Err(RequestError::Io(e)) if retries < SEND_RETRY => {
+                retries += 1;
+                warn!("Telegram request has io error: {}, retrying ...", e);
+                sleep(SEND_RETRY_DELAY * retries).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// for the values inside `href="..."`
+pub fn escape_attr(s: &str) -> String {
+    escape_html(s).replace('"', "&quot;")
 }
 
 pub async fn send(
     telegram_sends: &mut [TelegramSend],
-    bot: &AutoSend<Bot>,
-    chat_id: i64,
+    bot: &Bot,
+    route: &Route,
     client: &Client,
 ) -> Result<()> {
     async fn send_bytes_photo(
         url: &str,
         client: &Client,
         msg: &str,
-        chat_id: i64,
-        bot: &AutoSend<Bot>,
-    ) -> Result<()> {
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
         let photo = get_photo(url, client).await?;
-        send_photo_with_bytes!(bot, chat_id, photo, msg)?;
 
-        Ok(())
+        Ok(send_photo_with_bytes!(bot, route, photo, msg)?)
     }
 
-    async fn send_bytes_groups(
-        urls: &[String],
-        msg: &str,
+    async fn send_cover(
+        url: &str,
         client: &Client,
-        bot: &AutoSend<Bot>,
-        chat_id: i64,
-    ) -> Result<()> {
-        let mut groups = Vec::new();
-        for url in urls {
-            let photo = get_photo(url, client).await?;
-            groups.push(InputMedia::Photo(InputMediaPhoto {
-                media: InputFile::memory(photo),
-                caption: Some(msg.to_string()),
-                parse_mode: Some(ParseMode::Html),
-                caption_entities: None,
-                has_spoiler: false,
-            }));
+        msg: &str,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
+        match send_photo!(bot, route, url, msg.to_string()) {
+            Ok(msg) => Ok(msg),
+            Err(e) => {
+                warn!(
+                    "Telegram send photo has error! {}, Trying covert image ...",
+                    e
+                );
+                send_bytes_photo(url, client, msg, route, bot).await
+            }
         }
-        send_group!(bot, chat_id, groups)?;
-
-        Ok(())
     }
 
-    telegram_sends.reverse();
+    async fn send_bytes_video(
+        url: &str,
+        client: &Client,
+        msg: &str,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
+        let video = get_media(url, client).await?;
+
+        Ok(send_video_with_bytes!(bot, route, video, msg)?)
+    }
 
-    for i in telegram_sends {
-        if let Some(photo) = &i.photo {
-            if let Err(e) = send_photo!(bot, chat_id, photo, i.msg) {
+    async fn send_file(
+        url: &str,
+        client: &Client,
+        msg: &str,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
+        match send_document!(bot, route, InputFile::url(Url::parse(url)?), msg) {
+            Ok(msg) => Ok(msg),
+            Err(e) => {
                 warn!(
-                    "Telegram send photo has error! {}, Trying covert image ...",
+                    "Telegram send document has error! {}, Trying upload file ...",
                     e
                 );
-                if let Err(e) = send_bytes_photo(photo, client, &i.msg, chat_id, bot).await {
-                    warn!(
-                        "Telegram send convert photo has error! {}, Trying only send text msg ...",
-                        e
-                    );
-                    send_msg!(bot, chat_id, i.msg);
+                let document =
+                    InputFile::memory(get_media(url, client).await?).file_name(file_name(url));
+                Ok(send_document!(bot, route, document, msg)?)
+            }
+        }
+    }
+
+    async fn send_single(
+        media: &Media,
+        client: &Client,
+        msg: &str,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
+        match media {
+            Media::Photo(url) => send_cover(url, client, msg, route, bot).await,
+            Media::Video { url, cover } => {
+                match send_bytes_video(url, client, msg, route, bot).await {
+                    Ok(msg) => Ok(msg),
+                    Err(e) => match cover {
+                        Some(cover) => {
+                            warn!(
+                                "Telegram send video has error! {}, Trying only send cover ...",
+                                e
+                            );
+                            send_cover(cover, client, msg, route, bot).await
+                        }
+                        None => Err(e),
+                    },
+                }
+            }
+            Media::Document(url) => send_file(url, client, msg, route, bot).await,
+        }
+    }
+
+    // uploads everything by bytes if `upload`, otherwise telegram fetches the urls,
+    // videos are always uploaded since the cdn checks the referer
+    async fn input_media(media: &Media, upload: bool, client: &Client) -> Result<InputMedia> {
+        let input_media = match media {
+            Media::Photo(url) if upload => InputMedia::Photo(InputMediaPhoto::new(
+                InputFile::memory(get_photo(url, client).await?),
+            )),
+            Media::Photo(url) => {
+                InputMedia::Photo(InputMediaPhoto::new(InputFile::url(Url::parse(url)?)))
+            }
+            Media::Video { url, cover } => match get_media(url, client).await {
+                Ok(video) => {
+                    let mut video =
+                        InputMediaVideo::new(InputFile::memory(video).file_name("video.mp4"));
+                    video.supports_streaming = Some(true);
+                    InputMedia::Video(video)
                 }
+                Err(e) => match cover {
+                    Some(cover) if upload => {
+                        warn!("Can not get video {}, using the cover: {}", url, e);
+                        InputMedia::Photo(InputMediaPhoto::new(InputFile::memory(
+                            get_photo(cover, client).await?,
+                        )))
+                    }
+                    _ => return Err(e),
+                },
+            },
+            Media::Document(url) if upload => InputMedia::Document(InputMediaDocument::new(
+                InputFile::memory(get_media(url, client).await?).file_name(file_name(url)),
+            )),
+            Media::Document(url) => {
+                InputMedia::Document(InputMediaDocument::new(InputFile::url(Url::parse(url)?)))
+            }
+        };
+
+        Ok(input_media)
+    }
+
+    async fn send_media_group(
+        album: &[Media],
+        client: &Client,
+        msg: Option<&str>,
+        upload: bool,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Option<Message>> {
+        let mut items = vec![];
+        for media in album {
+            match input_media(media, upload, client).await {
+                Ok(input) => items.push((media, input)),
+                // skip the broken one rather than the whole album
+                Err(e) if upload => warn!("Telegram album skips a media: {}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        // a media group needs at least 2 items
+        match items.as_slice() {
+            // all broken, skipped like each of them
+            [] => return Ok(None),
+            [(media, _)] => {
+                let msg = send_single(media, client, msg.unwrap_or_default(), route, bot).await?;
+                return Ok(Some(msg));
             }
-        } else if let Some(photos) = &i.photos {
-            let mut groups = vec![];
-            for j in photos {
-                groups.push(InputMedia::Photo(InputMediaPhoto {
-                    media: InputFile::url(Url::parse(j)?),
-                    caption: Some(i.msg.clone()),
-                    parse_mode: Some(ParseMode::Html),
-                    caption_entities: None,
-                    has_spoiler: false,
-                }));
-            }
-            if let Err(e) = send_group!(bot, chat_id, groups) {
+            _ => (),
+        }
+        let mut groups = items.into_iter().map(|x| x.1).collect::<Vec<_>>();
+        // the caption of the first item is the caption of the album
+        if let (Some(first), Some(msg)) = (groups.first_mut(), msg) {
+            set_caption(first, msg);
+        }
+
+        let msgs = send_group!(bot, route, groups)?;
+
+        msgs.into_iter()
+            .next()
+            .map(Some)
+            .ok_or_else(|| anyhow!("Telegram returns empty media group!"))
+    }
+
+    async fn send_album(
+        album: &[Media],
+        client: &Client,
+        msg: Option<&str>,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Option<Message>> {
+        if let [media] = album {
+            let msg = send_single(media, client, msg.unwrap_or_default(), route, bot).await?;
+            return Ok(Some(msg));
+        }
+
+        match send_media_group(album, client, msg, false, route, bot).await {
+            Ok(msg) => Ok(msg),
+            Err(e) => {
                 warn!(
                     "Telegram send group has error! {}, Trying convert image ...",
                     e
                 );
+                send_media_group(album, client, msg, true, route, bot).await
+            }
+        }
+    }
+
+    async fn send_bytes_animations(urls: &[String], client: &Client, route: &Route, bot: &Bot) {
+        for url in urls {
+            let res = match get_media(url, client).await {
+                Ok(animation) => send_animation_with_bytes!(bot, route, animation)
+                    .map(|_| ())
+                    .map_err(|e| anyhow!("{}", e)),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                warn!("Telegram send animation {} has error! {}", url, e);
+            }
+        }
+    }
+
+    // long text is sent in several messages, returns the first one
+    async fn send_text(
+        msg: &str,
+        reply_to: Option<i32>,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<Message> {
+        let mut first: Option<Message> = None;
+        for part in split_html(msg, MESSAGE_LIMIT, MESSAGE_LIMIT) {
+            let reply_to = if first.is_none() { reply_to } else { None };
+            let msg = send_msg!(bot, route, part, reply_to)?;
+            first.get_or_insert(msg);
+        }
+
+        first.ok_or_else(|| anyhow!("Telegram message is empty!"))
+    }
+
+    // the rest of a truncated caption
+    async fn send_follow_up(
+        rest: &[String],
+        reply_to: i32,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<()> {
+        for part in rest {
+            send_msg!(bot, route, part.clone(), Some(reply_to))?;
+        }
 
-                if let Err(e) = send_bytes_groups(photos, &i.msg, client, bot, chat_id).await {
-                    warn!(
-                        "Telegram send convert group has error! {}, Trying only send text msg ...",
-                        e
-                    );
-                    send_msg!(bot, chat_id, i.msg);
+        Ok(())
+    }
+
+    async fn send_one(
+        i: &mut TelegramSend,
+        client: &Client,
+        route: &Route,
+        bot: &Bot,
+    ) -> Result<()> {
+        let target = route.target();
+        let reply_to = i.reply_to.get(&target).copied();
+
+        // the chat only wants text
+        if !route.images {
+            let msg = send_text(&i.msg, reply_to, route, bot).await?;
+            i.message_ids.insert(target, msg.id.0);
+            return Ok(());
+        }
+
+        let (caption, rest) = split_caption(&i.msg);
+
+        // a failed album fails the send, the albums before it are not sent again in the retry
+        let sent = i.albums_sent.get(&target).copied().unwrap_or_default();
+        for (n, album) in i.albums().iter().enumerate().skip(sent) {
+            // the caption goes with the first album sent
+            let first = i.message_ids.get(&target).copied();
+            let msg = if first.is_none() {
+                Some(caption.as_str())
+            } else {
+                None
+            };
+            match send_album(album, client, msg, route, bot).await {
+                Ok(Some(msg)) => {
+                    if first.is_none() {
+                        i.message_ids.insert(target.clone(), msg.id.0);
+                    }
                 }
-                return Ok(());
+                Ok(None) => warn!("Telegram send album skips all broken media!"),
+                // the media is gone, retrying does not help
+                Err(e) if is_gone(&e) => warn!("Telegram send album skips gone media! {}", e),
+                Err(e) => return Err(e),
             }
-            if photos.len() > 1 {
-                send_msg!(bot, chat_id, i.msg);
+            i.albums_sent.insert(target.clone(), n + 1);
+        }
+
+        match i.message_ids.get(&target) {
+            Some(id) => send_follow_up(&rest, *id, route, bot).await?,
+            None => {
+                let msg = send_text(&i.msg, reply_to, route, bot).await?;
+                i.message_ids.insert(target, msg.id.0);
             }
+        }
+
+        if let Some(animations) = &i.animations {
+            send_bytes_animations(animations, client, route, bot).await;
+        }
+
+        Ok(())
+    }
+
+    // a failed message should not stop the following ones
+    let mut result = Ok(());
+    for i in telegram_sends.iter_mut().rev() {
+        if let Err(e) = send_one(i, client, route, bot).await {
+            error!("Telegram send message has error! {}", e);
+            result = Err(e);
+        }
+    }
+
+    result
+}
+
+fn set_caption(media: &mut InputMedia, msg: &str) {
+    let (caption, parse_mode) = match media {
+        InputMedia::Photo(x) => (&mut x.caption, &mut x.parse_mode),
+        InputMedia::Video(x) => (&mut x.caption, &mut x.parse_mode),
+        InputMedia::Animation(x) => (&mut x.caption, &mut x.parse_mode),
+        InputMedia::Audio(x) => (&mut x.caption, &mut x.parse_mode),
+        InputMedia::Document(x) => (&mut x.caption, &mut x.parse_mode),
+    };
+    *caption = Some(msg.to_string());
+    *parse_mode = Some(ParseMode::Html);
+}
+
+fn file_name(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|x| x.path_segments()?.next_back().map(|x| x.to_string()))
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "file".to_string())
+}
+
+// the cdn answers 403, 404 or 410 for deleted media
+fn is_gone(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|x| x.status())
+        .map(|x| x.is_client_error())
+        .unwrap_or(false)
+}
+
+// the caption is cut to fit telegram, the rest follows in messages
+fn split_caption(msg: &str) -> (String, Vec<String>) {
+    if text_len(msg) <= CAPTION_LIMIT {
+        return (msg.to_string(), vec![]);
+    }
+
+    let mut parts = split_html(msg, CAPTION_LIMIT - 1, MESSAGE_LIMIT);
+    let mut caption = parts.remove(0);
+    caption.push('…');
+
+    (caption, parts)
+}
+
+// tags, html entities or single chars
+fn html_tokens(html: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' => rest.find('>').map(|x| x + 1),
+            '&' => rest
+                .find(';')
+                .filter(|x| {
+                    *x <= 10 && rest[1..*x].chars().all(|c| c.is_alphanumeric() || c == '#')
+                })
+                .map(|x| x + 1),
+            _ => None,
+        }
+        .unwrap_or(c.len_utf8());
+        let (token, remain) = rest.split_at(end);
+        tokens.push(token);
+        rest = remain;
+    }
+
+    tokens
+}
+
+// telegram counts the length in utf-16 after parsing the html
+fn token_len(token: &str) -> usize {
+    if token.starts_with('<') && token.ends_with('>') {
+        0
+    } else if token.starts_with('&') && token.ends_with(';') {
+        1
+    } else {
+        token.encode_utf16().count()
+    }
+}
+
+fn text_len(html: &str) -> usize {
+    html_tokens(html).into_iter().map(token_len).sum()
+}
+
+struct HtmlSplitter<'a> {
+    chunks: Vec<String>,
+    chunk: String,
+    len: usize,
+    // name and the opening tag of the unclosed tags
+    open: Vec<(&'a str, &'a str)>,
+    first_limit: usize,
+    limit: usize,
+}
+
+impl<'a> HtmlSplitter<'a> {
+    fn limit(&self) -> usize {
+        if self.chunks.is_empty() {
+            self.first_limit
         } else {
-            send_msg!(bot, chat_id, i.msg);
+            self.limit
+        }
+    }
+
+    fn push(&mut self, token: &'a str) {
+        let len = token_len(token);
+        if len > 0 && self.len + len > self.limit() {
+            self.flush();
+        }
+        self.chunk.push_str(token);
+        self.len += len;
+
+        if len == 0 && !token.ends_with("/>") {
+            let name = token
+                .trim_start_matches('<')
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '>')
+                .next()
+                .unwrap_or_default();
+            if token.starts_with("</") {
+                if let Some(pos) = self.open.iter().rposition(|x| x.0 == name) {
+                    self.open.remove(pos);
+                }
+            } else {
+                self.open.push((name, token));
+            }
         }
     }
 
+    // closes the unclosed tags and opens them again in the next chunk
+    fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        for (name, _) in self.open.iter().rev() {
+            self.chunk.push_str(&format!("</{}>", name));
+        }
+        let chunk = self.open.iter().map(|x| x.1).collect::<String>();
+        self.chunks.push(std::mem::replace(&mut self.chunk, chunk));
+        self.len = 0;
+    }
+}
+
+// splits at line breaks when possible, and never inside a tag or an entity
+pub fn split_html(html: &str, first_limit: usize, limit: usize) -> Vec<String> {
+    let tokens = html_tokens(html);
+    let mut splitter = HtmlSplitter {
+        chunks: vec![],
+        chunk: String::new(),
+        len: 0,
+        open: vec![],
+        first_limit,
+        limit,
+    };
+
+    for line in tokens.split_inclusive(|x| *x == "\n") {
+        let len = line.iter().map(|x| token_len(x)).sum::<usize>();
+        if splitter.len + len > splitter.limit() {
+            splitter.flush();
+        }
+        for token in line {
+            splitter.push(token);
+        }
+    }
+    splitter.flush();
+
+    if splitter.chunks.is_empty() {
+        splitter.chunks.push(splitter.chunk);
+    }
+
+    splitter.chunks
+}
+
+// edits the caption of a media message, or the text if it is sent as text,
+// a message already edited to the same content counts as edited
+pub async fn edit_message(bot: &Bot, route: &Route, message_id: i32, msg: &str) -> Result<()> {
+    let req = bot
+        .edit_message_caption(Recipient::Id(ChatId(route.chat_id)), MessageId(message_id))
+        .caption(msg)
+        .parse_mode(ParseMode::Html);
+    match queue(route.chat_id, 1, req).await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => return Ok(()),
+        Err(e) => warn!(
+            "Telegram edit caption has error! {}, Trying edit text ...",
+            e
+        ),
+    }
+
+    let req = bot
+        .edit_message_text(
+            Recipient::Id(ChatId(route.chat_id)),
+            MessageId(message_id),
+            msg,
+        )
+        .parse_mode(ParseMode::Html);
+    match queue(route.chat_id, 1, req).await {
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// plain text reply, waits in the queue of the chat like the notifications
+pub async fn reply_text(bot: &Bot, chat_id: i64, reply_to: i32, text: &str) -> Result<()> {
+    let route = Route::new(chat_id);
+    let mut reply_to = Some(reply_to);
+    for part in split_html(&escape_html(text), MESSAGE_LIMIT, MESSAGE_LIMIT) {
+        send_msg!(bot, route, part, reply_to.take())?;
+    }
+
+    Ok(())
+}
+
+pub async fn send_photo_bytes(bot: &Bot, chat_id: i64, photo: Vec<u8>, msg: &str) -> Result<()> {
+    let route = Route::new(chat_id);
+    send_photo_with_bytes!(bot, route, photo, msg)?;
+
+    Ok(())
+}
+
+pub async fn show_qrcode(
+    content: &str,
+    hint: &str,
+    bot: Option<&Bot>,
+    admin_chat_id: Option<i64>,
+) -> Result<()> {
+    let code = QrCode::new(content.as_bytes())?;
+    println!("{}:\n{}", hint, code.render::<unicode::Dense1x2>().build());
+
+    if let (Some(bot), Some(chat_id)) = (bot, admin_chat_id) {
+        send_photo_bytes(bot, chat_id, qrcode_png(&code)?, hint).await?;
+    }
+
     Ok(())
 }
 
+fn qrcode_png(code: &QrCode) -> Result<Vec<u8>> {
+    const SCALE: u32 = 8;
+    const BORDER: u32 = 4;
+
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (width + BORDER * 2) * SCALE;
+    let img = GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / SCALE, y / SCALE);
+        if x < BORDER || y < BORDER || x >= width + BORDER || y >= width + BORDER {
+            return Luma([255]);
+        }
+        match colors[((y - BORDER) * width + x - BORDER) as usize] {
+            Color::Dark => Luma([0]),
+            Color::Light => Luma([255]),
+        }
+    });
+
+    let mut bytes = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), image::ImageOutputFormat::Png)?;
+
+    Ok(bytes)
+}
+
+pub async fn ask_admin(
+    bot: &Bot,
+    chat_id: i64,
+    question: &str,
+    timeout: Duration,
+) -> Result<String> {
+    if DISPATCHING.load(Ordering::SeqCst) {
+        let (tx, rx) = oneshot::channel();
+        *ADMIN_REPLY.lock().unwrap() = Some(tx);
+        let route = Route::new(chat_id);
+        send_msg!(bot, route, question.to_string(), None::<i32>)?;
+
+        return tokio::time::timeout(timeout, rx)
+            .await
+            .map_err(|_| anyhow!("Waiting for admin reply timeout!"))?
+            .map_err(|e| anyhow!(e));
+    }
+
+    // skip the updates before asking, only the reply after the question is wanted
+    let mut offset = bot
+        .get_updates()
+        .offset(-1)
+        .await?
+        .last()
+        .map(|x| x.id + 1)
+        .unwrap_or(0);
+
+    let route = Route::new(chat_id);
+    send_msg!(bot, route, question.to_string(), None::<i32>)?;
+
+    tokio::time::timeout(timeout, async {
+        loop {
+            let updates = bot.get_updates().offset(offset).timeout(10).await?;
+            for i in updates {
+                offset = i.id + 1;
+                if let UpdateKind::Message(msg) = i.kind {
+                    if msg.chat.id != ChatId(chat_id) {
+                        continue;
+                    }
+                    if let Some(text) = msg.text() {
+                        return Ok(text.trim().to_string());
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("Waiting for admin reply timeout!"))?
+}
+
+pub fn set_dispatching() {
+    DISPATCHING.store(true, Ordering::SeqCst);
+}
+
+// returns false if nobody is waiting for the reply
+pub fn reply_admin(text: &str) -> bool {
+    match ADMIN_REPLY.lock().unwrap().take() {
+        Some(tx) => tx.send(text.trim().to_string()).is_ok(),
+        None => false,
+    }
+}
+
+pub async fn get_media(url: &str, client: &Client) -> Result<Vec<u8>> {
+    let mut req = client.get(url);
+    if let Some(referer) = media_referer(url) {
+        req = req.header("Referer", referer);
+    }
+    let resp = req.send().await?.error_for_status()?;
+    if resp.content_length().unwrap_or_default() as usize > MAX_UPLOAD_SIZE {
+        bail!("{} is too large to upload!", url);
+    }
+    let bytes = resp.bytes().await?;
+    if bytes.len() > MAX_UPLOAD_SIZE {
+        bail!("{} is too large to upload!", url);
+    }
+
+    Ok(bytes.to_vec())
+}
+
+fn media_referer(url: &str) -> Option<&'static str> {
+    let host = Url::parse(url).ok()?.host_str()?.to_string();
+    if host.ends_with("weibo.com") || host.ends_with("weibocdn.com") || host.ends_with("sinaimg.cn")
+    {
+        Some("https://m.weibo.cn/")
+    } else if host.ends_with("hdslb.com") || host.ends_with("bilivideo.com") {
+        Some("https://www.bilibili.com/")
+    } else {
+        None
+    }
+}
+
 pub async fn get_photo(url: &str, client: &Client) -> Result<Vec<u8>> {
     let resp = client
         .get(url)
@@ -170,6 +983,83 @@ pub async fn get_photo(url: &str, client: &Client) -> Result<Vec<u8>> {
     Ok(bytes)
 }
 
+#[test]
+fn test_media_referer() {
+    assert_eq!(
+        media_referer("https://f.video.weibocdn.com/o0/abc.mp4?label=mp4_720p"),
+        Some("https://m.weibo.cn/")
+    );
+    assert_eq!(media_referer("https://example.com/a.mp4"), None);
+}
+
+#[test]
+fn test_reserve() {
+    let first = reserve(-42, 1);
+    let second = reserve(-42, 2);
+    let third = reserve(-42, 1);
+    assert!(second >= first + GROUP_SEND_INTERVAL);
+    assert!(third >= second + GROUP_SEND_INTERVAL * 2);
+
+    delay(-42, Duration::from_secs(60));
+    assert!(reserve(-42, 1) >= first + Duration::from_secs(59));
+}
+
+#[test]
+fn test_albums() {
+    assert_eq!(album_sizes(0), Vec::<usize>::new());
+    assert_eq!(album_sizes(1), vec![1]);
+    assert_eq!(album_sizes(10), vec![10]);
+    assert_eq!(album_sizes(11), vec![6, 5]);
+    assert_eq!(album_sizes(18), vec![9, 9]);
+    assert_eq!(album_sizes(21), vec![7, 7, 7]);
+
+    let telegram_send = TelegramSend {
+        video: Some("https://example.com/a.mp4".to_string()),
+        photo: Some("https://example.com/cover.jpg".to_string()),
+        photos: Some(
+            (0..18)
+                .map(|x| format!("https://example.com/{}.jpg", x))
+                .collect(),
+        ),
+        documents: Some(vec!["https://example.com/a.pdf".to_string()]),
+        ..Default::default()
+    };
+    let albums = telegram_send.albums();
+    assert_eq!(
+        albums.iter().map(|x| x.len()).collect::<Vec<_>>(),
+        vec![10, 9, 1]
+    );
+    assert!(matches!(albums[0][0], Media::Video { .. }));
+    assert!(matches!(albums[2][0], Media::Document(_)));
+    assert_eq!(file_name("https://example.com/files/a.pdf?x=1"), "a.pdf");
+}
+
+#[test]
+fn test_split_html() {
+    assert_eq!(split_html("小熊猫", 10, 10), vec!["小熊猫"]);
+    assert_eq!(
+        split_html("<b>abc\ndef</b>\nghi", 5, 5),
+        vec!["<b>abc\n</b>", "<b>def</b>\n", "ghi"]
+    );
+    // long line is cut inside, but not inside an entity or a tag
+    assert_eq!(
+        split_html("<a href=\"https://t.me\">a&amp;bcd</a>", 3, 3),
+        vec![
+            "<a href=\"https://t.me\">a&amp;b</a>",
+            "<a href=\"https://t.me\">cd</a>"
+        ]
+    );
+    assert_eq!(text_len("<b>a&lt;b</b>😀"), 5);
+
+    let msg = format!("<b>小熊猫</b>\n{}", "字".repeat(2000));
+    let (caption, rest) = split_caption(&msg);
+    assert!(text_len(&caption) <= CAPTION_LIMIT);
+    assert!(caption.ends_with('…'));
+    assert_eq!(rest.len(), 1);
+    assert_eq!(text_len(&caption) - 1 + text_len(&rest[0]), text_len(&msg));
+    assert_eq!(split_caption("小熊猫"), ("小熊猫".to_string(), vec![]));
+}
+
 #[tokio::test]
 async fn test() {
     let client = reqwest::Client::new();
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use serde::{Deserialize, Serialize};
+
+const SNAPSHOT_LIMIT: usize = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub id: u64,
+    pub hash: String,
+    pub text: String,
+    pub media: Vec<String>,
+    pub url: String,
+    // route target -> message id of the notification
+    #[serde(default)]
+    pub message_ids: HashMap<String, i32>,
+    // counts the edits, tells the notifications of the same edit apart
+    #[serde(default)]
+    pub revision: u32,
+}
+
+impl Snapshot {
+    pub fn new(id: u64, text: String, media: Vec<String>, url: String) -> Self {
+        let hash = format!(
+            "{:x}",
+            md5::compute(format!("{}\n{}", text, media.join("\n")))
+        );
+
+        Snapshot {
+            id,
+            hash,
+            text,
+            media,
+            url,
+            message_ids: HashMap::new(),
+            revision: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub edited: Vec<(Snapshot, Snapshot)>,
+    pub missing: Vec<Snapshot>,
+}
+
+pub async fn save(con: &MultiplexedConnection, key: &str, snapshot: &Snapshot) -> Result<()> {
+    let mut con = con.clone();
+    con.hset::<_, _, _, ()>(key, snapshot.id, serde_json::to_string(snapshot)?)
+        .await?;
+
+    let mut ids: Vec<u64> = con.hkeys(key).await?;
+    if ids.len() > SNAPSHOT_LIMIT {
+        ids.sort_unstable();
+        let outdated = &ids[..ids.len() - SNAPSHOT_LIMIT];
+        con.hdel::<_, _, ()>(key, outdated).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn remove(con: &MultiplexedConnection, key: &str, id: u64) -> Result<()> {
+    let mut con = con.clone();
+    con.hdel::<_, _, ()>(key, id).await?;
+
+    Ok(())
+}
+
+pub async fn compare(
+    con: &MultiplexedConnection,
+    key: &str,
+    current: &[Snapshot],
+) -> Result<Changes> {
+    let mut con = con.clone();
+    let saved: HashMap<u64, String> = con.hgetall(key).await?;
+    let min_id = current.iter().map(|x| x.id).min();
+
+    let mut changes = Changes::default();
+    for (id, v) in saved {
+        let old = serde_json::from_str::<Snapshot>(&v)?;
+        match current.iter().find(|x| x.id == id) {
+            // not saved here, the new one is saved once the edit is delivered
+            Some(new) if new.hash != old.hash => {
+                let new = Snapshot {
+                    message_ids: old.message_ids.clone(),
+                    revision: old.revision + 1,
+                    ..new.clone()
+                };
+                changes.edited.push((old, new));
+            }
+            Some(_) => (),
+            // newer than the oldest post in this page but disappeared
+            None if min_id.map(|x| id > x).unwrap_or(false) => changes.missing.push(old),
+            // fell off the page, can not be tracked any more
+            None => remove(&con, key, id).await?,
+        }
+    }
+
+    changes.edited.sort_by_key(|x| x.0.id);
+    changes.missing.sort_by_key(|x| x.id);
+
+    Ok(changes)
+}
+
+pub fn diff(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // longest common subsequence of lines
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            result.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            result.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+
+    result.join("\n")
+}
+
+#[test]
+fn test_diff() {
+    assert_eq!(
+        diff("小熊猫\n今天也很可爱\n晚安", "小熊猫\n今天超级可爱\n晚安"),
+        "- 今天也很可爱\n+ 今天超级可爱"
+    );
+    assert_eq!(diff("a", "a\nb"), "+ b");
+    assert_eq!(diff("a\nb", "b"), "- a");
+    assert_eq!(diff("a", "a"), "");
+}
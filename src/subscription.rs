@@ -0,0 +1,282 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    BiliDynamic,
+    BiliVideo,
+    BiliReservation,
+    BiliComment,
+    Live,
+    Weibo,
+}
+
+pub const KINDS: &[Kind] = &[
+    Kind::BiliDynamic,
+    Kind::BiliVideo,
+    Kind::BiliReservation,
+    Kind::BiliComment,
+    Kind::Live,
+    Kind::Weibo,
+];
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::BiliDynamic => "bili_dynamic",
+            Kind::BiliVideo => "bili_video",
+            Kind::BiliReservation => "bili_reservation",
+            Kind::BiliComment => "bili_comment",
+            Kind::Live => "live",
+            Kind::Weibo => "weibo",
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("subscriptions-{}", self.name())
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        KINDS
+            .iter()
+            .find(|x| x.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown subscription type {}, available: {}",
+                    s,
+                    KINDS
+                        .iter()
+                        .map(|x| x.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+// where the notifications of a subscription go, written as `chat_id[/thread_id][:silent][:noimage]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub chat_id: i64,
+    pub thread_id: Option<i32>,
+    pub silent: bool,
+    pub images: bool,
+}
+
+impl Route {
+    pub fn new(chat_id: i64) -> Self {
+        Route {
+            chat_id,
+            thread_id: None,
+            silent: false,
+            images: true,
+        }
+    }
+
+    // identifies the chat (and topic) regardless of the options
+    pub fn target(&self) -> String {
+        match self.thread_id {
+            Some(thread_id) => format!("{}/{}", self.chat_id, thread_id),
+            None => self.chat_id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.target())?;
+        if self.silent {
+            write!(f, ":silent")?;
+        }
+        if !self.images {
+            write!(f, ":noimage")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Route {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut split = s.trim().split(':');
+        let target = split.next().unwrap_or_default();
+        let (chat_id, thread_id) = match target.split_once('/') {
+            Some((chat_id, thread_id)) => (chat_id, Some(thread_id.parse::<i32>()?)),
+            None => (target, None),
+        };
+
+        let mut route = Route {
+            thread_id,
+            ..Route::new(chat_id.parse::<i64>()?)
+        };
+        for option in split {
+            match option {
+                "silent" => route.silent = true,
+                "noimage" => route.images = false,
+                _ => bail!("Unknown chat option {}, available: silent, noimage", option),
+            }
+        }
+
+        Ok(route)
+    }
+}
+
+pub fn parse_routes(s: &str) -> Result<Vec<Route>> {
+    s.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<Route>())
+        .collect()
+}
+
+// returns false if the target is already subscribed
+pub async fn add(con: &MultiplexedConnection, kind: Kind, id: &str) -> Result<bool> {
+    let mut con = con.clone();
+    let added: u64 = con.sadd(kind.key(), id).await?;
+
+    Ok(added > 0)
+}
+
+// targets from env are only added once, so unsubscribing them survives a restart
+pub async fn import(con: &MultiplexedConnection, kind: Kind, id: &str) -> Result<bool> {
+    let mut con = con.clone();
+    let imported: u64 = con.sadd(format!("{}-imported", kind.key()), id).await?;
+    if imported == 0 {
+        return Ok(false);
+    }
+
+    add(&con, kind, id).await
+}
+
+// returns false if the target is not subscribed
+pub async fn remove(con: &MultiplexedConnection, kind: Kind, id: &str) -> Result<bool> {
+    let mut con = con.clone();
+    let removed: u64 = con.srem(kind.key(), id).await?;
+    con.del::<_, ()>(routes_key(kind, id)).await?;
+
+    Ok(removed > 0)
+}
+
+pub async fn list(con: &MultiplexedConnection, kind: Kind) -> Result<Vec<String>> {
+    let mut con = con.clone();
+    let mut ids: Vec<String> = con.smembers(kind.key()).await?;
+    ids.sort_unstable();
+
+    Ok(ids)
+}
+
+pub async fn list_ids(con: &MultiplexedConnection, kind: Kind) -> Result<Vec<u64>> {
+    let ids = list(con, kind).await?;
+
+    Ok(ids.iter().filter_map(|x| x.parse::<u64>().ok()).collect())
+}
+
+fn routes_key(kind: Kind, id: &str) -> String {
+    format!("{}-{}-routes", kind.key(), id)
+}
+
+// the same chat (and topic) is replaced, so the options can be changed
+pub async fn add_route(
+    con: &MultiplexedConnection,
+    kind: Kind,
+    id: &str,
+    route: &Route,
+) -> Result<()> {
+    let mut con = con.clone();
+    let key = routes_key(kind, id);
+    con.hset::<_, _, _, ()>(key, route.target(), route.to_string())
+        .await?;
+
+    Ok(())
+}
+
+// returns false if the chat is not routed
+pub async fn remove_route(
+    con: &MultiplexedConnection,
+    kind: Kind,
+    id: &str,
+    route: &Route,
+) -> Result<bool> {
+    let mut con = con.clone();
+    let removed: u64 = con.hdel(routes_key(kind, id), route.target()).await?;
+
+    Ok(removed > 0)
+}
+
+pub async fn list_routes(con: &MultiplexedConnection, kind: Kind, id: &str) -> Result<Vec<Route>> {
+    let mut con = con.clone();
+    let routes: Vec<String> = con.hvals(routes_key(kind, id)).await?;
+    let mut routes = routes
+        .iter()
+        .map(|x| x.parse::<Route>())
+        .collect::<Result<Vec<_>>>()?;
+    routes.sort_unstable_by_key(|x| x.target());
+
+    Ok(routes)
+}
+
+// falls back to the default chats if the subscription has no chat of its own
+pub async fn routes(
+    con: &MultiplexedConnection,
+    kind: Kind,
+    id: &str,
+    default: &[Route],
+) -> Result<Vec<Route>> {
+    let routes = list_routes(con, kind, id).await?;
+    if routes.is_empty() {
+        return Ok(default.to_vec());
+    }
+
+    Ok(routes)
+}
+
+#[test]
+fn test_route() {
+    let route = "-1001675012012/12:silent:noimage".parse::<Route>().unwrap();
+    assert_eq!(
+        route,
+        Route {
+            chat_id: -1001675012012,
+            thread_id: Some(12),
+            silent: true,
+            images: false,
+        }
+    );
+    assert_eq!(route.to_string(), "-1001675012012/12:silent:noimage");
+    assert_eq!(route.target(), "-1001675012012/12");
+    assert_eq!(
+        parse_routes("123, 456:silent").unwrap(),
+        vec![
+            Route::new(123),
+            Route {
+                silent: true,
+                ..Route::new(456)
+            }
+        ]
+    );
+    assert!("123:loud".parse::<Route>().is_err());
+    assert!("abc".parse::<Route>().is_err());
+}
+
+#[test]
+fn test_kind() {
+    for kind in KINDS {
+        assert_eq!(kind.name().parse::<Kind>().unwrap(), *kind);
+    }
+    assert!("bilibili".parse::<Kind>().is_err());
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use reqwest::{header::HeaderMap, Client};
+use serde::Deserialize;
+
+use crate::{risk_control, wbi};
+
+#[derive(Debug, Deserialize)]
+struct ArcSearch {
+    data: ArcSearchData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArcSearchData {
+    list: ArcSearchList,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArcSearchList {
+    vlist: Vec<BiliVideoResult>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BiliVideoResult {
+    pub bvid: String,
+    pub author: String,
+    pub title: String,
+    pub description: String,
+    pub pic: String,
+    pub length: String,
+    pub created: u64,
+}
+
+impl BiliVideoResult {
+    pub fn url(&self) -> String {
+        format!("https://www.bilibili.com/video/{}", self.bvid)
+    }
+}
+
+pub async fn get_ailurus_videos(uid: u64, client: &Client) -> Result<Vec<BiliVideoResult>> {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        "Referer",
+        (format!("https://space.bilibili.com/{}/video", uid)).parse()?,
+    );
+    let query = wbi::sign(
+        &[
+            ("mid", uid.to_string()),
+            ("ps", "30".to_string()),
+            ("pn", "1".to_string()),
+            ("order", "pubdate".to_string()),
+        ],
+        client,
+    )
+    .await?;
+    let r: ArcSearch = risk_control::get_json(
+        client,
+        &format!("https://api.bilibili.com/x/space/wbi/arc/search?{}", query),
+        headers,
+    )
+    .await?;
+
+    Ok(r.data.list.vlist)
+}
+
+#[tokio::test]
+async fn test() {
+    let client = Client::new();
+    let videos = get_ailurus_videos(1501380958, &client).await.unwrap();
+    dbg!(videos);
+}
@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+const NAV_URL: &str = "https://api.bilibili.com/x/web-interface/nav";
+const MIXIN_KEY_REFRESH: Duration = Duration::from_secs(60 * 60);
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+#[derive(Debug, Deserialize)]
+struct Nav {
+    data: NavData,
+}
+
+#[derive(Debug, Deserialize)]
+struct NavData {
+    wbi_img: WbiImg,
+}
+
+#[derive(Debug, Deserialize)]
+struct WbiImg {
+    img_url: String,
+    sub_url: String,
+}
+
+lazy_static! {
+    static ref MIXIN_KEY: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+}
+
+pub async fn sign(params: &[(&str, String)], client: &Client) -> Result<String> {
+    let mixin_key = get_mixin_key(client).await?;
+    let wts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    Ok(sign_with_key(params, &mixin_key, wts))
+}
+
+async fn get_mixin_key(client: &Client) -> Result<String> {
+    let mut cache = MIXIN_KEY.lock().await;
+    if let Some((key, fetched_at)) = &*cache {
+        if fetched_at.elapsed() < MIXIN_KEY_REFRESH {
+            return Ok(key.clone());
+        }
+    }
+
+    // nav returns code -101 when not logged in but still carries wbi_img
+    let nav = client
+        .get(NAV_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Nav>()
+        .await?;
+
+    let key = mixin_key(
+        &key_from_url(&nav.data.wbi_img.img_url)?,
+        &key_from_url(&nav.data.wbi_img.sub_url)?,
+    );
+    *cache = Some((key.clone(), Instant::now()));
+
+    Ok(key)
+}
+
+fn key_from_url(url: &str) -> Result<String> {
+    url.rsplit_once('/')
+        .and_then(|(_, x)| x.split_once('.'))
+        .map(|(x, _)| x.to_string())
+        .ok_or_else(|| anyhow!("Can not get wbi key from {}", url))
+}
+
+fn mixin_key(img_key: &str, sub_key: &str) -> String {
+    let raw = format!("{}{}", img_key, sub_key).into_bytes();
+
+    MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|x| raw.get(*x).map(|x| *x as char))
+        .take(32)
+        .collect()
+}
+
+fn sign_with_key(params: &[(&str, String)], mixin_key: &str, wts: u64) -> String {
+    let mut params = params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect::<Vec<_>>();
+    params.push(("wts".to_string(), wts.to_string()));
+    params.sort();
+
+    let query = params
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .chars()
+                .filter(|x| !"!'()*".contains(*x))
+                .collect::<String>();
+            format!("{}={}", encode(k), encode(&v))
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let w_rid = format!("{:x}", md5::compute(format!("{}{}", query, mixin_key)));
+
+    format!("{}&w_rid={}", query, w_rid)
+}
+
+fn encode(s: &str) -> String {
+    let mut result = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    result
+}
+
+#[test]
+fn test_sign() {
+    let mixin_key = mixin_key(
+        "7cd084941338484aae1ad9425b84077c",
+        "4932caff0ff746eab6f01bf08b70ac45",
+    );
+    assert_eq!(mixin_key, "ea1db124af3c7062474693fa704f4ff8");
+
+    let query = sign_with_key(
+        &[
+            ("foo", "114".to_string()),
+            ("bar", "514".to_string()),
+            ("zab", "1919810".to_string()),
+        ],
+        &mixin_key,
+        1702204169,
+    );
+    assert_eq!(
+        query,
+        "bar=514&foo=114&wts=1702204169&zab=1919810&w_rid=8f6f2b5b3d485fe1886cec6a0be8c5d4"
+    );
+}
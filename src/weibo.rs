@@ -1,18 +1,48 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    fmt,
+    io::IsTerminal,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Result};
 use cookie_store::CookieStore;
 use fancy_regex::Regex;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
 use reqwest::{header::HeaderMap, Client, Response, Url};
 use reqwest_cookie_store::CookieStoreMutex;
 use rustyline::Editor;
-use serde::Deserialize;
-use tracing::info;
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Deserializer,
+};
+use teloxide::Bot;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::sender::{self, escape_html};
 
 const SEND_SMS_URL: &str = "https://passport.weibo.cn/signin/secondverify/ajsend";
 const CODE_CHECK_URL: &str = "https://passport.weibo.cn/signin/secondverify/ajcheck";
 const LOGIN_URL: &str = "https://passport.sina.cn/sso/login";
 const SEND_PRIVATE_MSG_URL: &str = "https://passport.weibo.cn/signin/secondverify/index";
+const CONFIG_URL: &str = "https://m.weibo.cn/api/config";
+const QRCODE_IMAGE_URL: &str = "https://passport.weibo.com/sso/v2/qrcode/image";
+const QRCODE_CHECK_URL: &str = "https://passport.weibo.com/sso/v2/qrcode/check";
+const QRCODE_REFERER: &str = "https://passport.weibo.com/sso/signin?entry=miniblog&source=miniblog";
+const QRCODE_TIMEOUT: Duration = Duration::from_secs(180);
+const GEN_VISITOR_URL: &str = "https://passport.weibo.com/visitor/genvisitor";
+const VISITOR_URL: &str = "https://passport.weibo.com/visitor/visitor";
+const VISITOR_FP: &str = r#"{"os":"1","browser":"Chrome86,0,4240,183","fonts":"undefined","screenInfo":"1920*1080*24","plugins":""}"#;
+const PROFILE_CONTAINER_PREFIX: &str = "100505";
+const INDEX_URL: &str = "https://m.weibo.cn/api/container/getIndex";
+const SHOW_URL: &str = "https://m.weibo.cn/statuses/show";
+const HOTFLOW_URL: &str = "https://m.weibo.cn/comments/hotflow";
+const EXTEND_URL: &str = "https://m.weibo.cn/statuses/extend";
+const PROFILE_INFO_URL: &str = "https://weibo.com/ajax/profile/info";
+const COOKIES_KEY: &str = "weibo-cookies";
+const VERIFY_CODE_TIMEOUT: Duration = Duration::from_secs(300);
+const VERIFY_CODE_RETRY: usize = 3;
 // const WEIBO_HOME_URL: &str = "https://weibo.com";
 const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.183 Safari/537.36";
 
@@ -59,11 +89,125 @@ struct VeriCheckData {
     url: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct QrCodeImage {
+    retcode: i64,
+    msg: String,
+    data: Option<QrCodeImageData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodeImageData {
+    qrid: String,
+    image: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodeCheck {
+    retcode: i64,
+    msg: String,
+    data: Option<QrCodeCheckData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QrCodeCheckData {
+    url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenVisitor {
+    retcode: i64,
+    msg: String,
+    data: Option<GenVisitorData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenVisitorData {
+    tid: String,
+    new_tid: bool,
+    confidence: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Incarnate {
+    retcode: i64,
+    msg: String,
+    data: Option<IncarnateData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IncarnateData {
+    sub: String,
+    subp: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeiboConfig {
+    data: WeiboConfigData,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeiboConfigData {
+    login: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeiboOk {
+    ok: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileInfo {
+    data: Option<ProfileInfoData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileInfoData {
+    user: ProfileInfoUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProfileInfoUser {
+    id: u64,
+}
+
+#[derive(Debug)]
+pub enum WeiboError {
+    SessionExpired,
+}
+
+impl fmt::Display for WeiboError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeiboError::SessionExpired => write!(f, "Weibo session is expired"),
+        }
+    }
+}
+
+impl std::error::Error for WeiboError {}
+
+#[derive(Clone)]
+pub enum WeiboLogin {
+    Password { account: String, password: String },
+    QrCode,
+    Visitor,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WeiboIndex {
     pub data: WeiboIndexData,
 }
 
+impl WeiboIndex {
+    pub fn is_empty(&self) -> bool {
+        self.data
+            .cards
+            .as_ref()
+            .map(|x| x.is_empty())
+            .unwrap_or(true)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WeiboIndexData {
     pub cards: Option<Vec<WeiboIndexDataCard>>,
@@ -86,35 +230,250 @@ pub struct WeiboIndexDataTabsInfoTab {
 pub struct WeiboIndexDataCard {
     pub card_type: Option<u64>,
     pub mblog: Option<WeiboIndexDataCardMblog>,
+    pub card_group: Option<Vec<WeiboIndexDataCard>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct WeiboIndexDataCardMblog {
     pub id: String,
-    pub user: WeiboIndexDataCardMblogUser,
+    pub user: Option<WeiboIndexDataCardMblogUser>,
     pub created_at: String,
     pub pics: Option<Vec<WeiboIndexDataCardMblogPic>>,
     pub text: String,
+    #[serde(rename = "isLongText", default)]
+    pub is_long_text: bool,
+    pub retweeted_status: Option<Box<WeiboIndexDataCardMblog>>,
+    pub page_info: Option<WeiboPageInfo>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
+pub struct WeiboPageInfo {
+    #[serde(rename = "type")]
+    pub page_type: Option<String>,
+    pub page_pic: Option<WeiboPageInfoPic>,
+    pub media_info: Option<WeiboPageInfoMedia>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WeiboPageInfoPic {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WeiboPageInfoMedia {
+    pub mp4_720p_mp4: Option<String>,
+    pub mp4_hd_url: Option<String>,
+    pub mp4_sd_url: Option<String>,
+    pub stream_url_hd: Option<String>,
+    pub stream_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct WeiboIndexDataCardMblogUser {
+    pub id: Option<u64>,
     pub screen_name: String,
 }
 
 #[derive(Debug, Deserialize)]
+struct HotFlow {
+    data: Option<HotFlowData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HotFlowData {
+    data: Vec<HotFlowComment>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct HotFlowComment {
+    id: String,
+    created_at: String,
+    text: String,
+    user: Option<WeiboIndexDataCardMblogUser>,
+    #[serde(default, deserialize_with = "sub_comments")]
+    comments: Vec<HotFlowComment>,
+}
+
+// weibo gives `false` instead of an empty list when there is no sub comment
+fn sub_comments<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<HotFlowComment>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SubComments {
+        List(Vec<HotFlowComment>),
+        Other(IgnoredAny),
+    }
+
+    Ok(match SubComments::deserialize(deserializer)? {
+        SubComments::List(x) => x,
+        SubComments::Other(_) => vec![],
+    })
+}
+
+impl HotFlowComment {
+    fn is_from(&self, uid: &str) -> bool {
+        self.user.as_ref().and_then(|x| x.id).map(|x| x.to_string()) == Some(uid.to_string())
+    }
+
+    fn user_name(&self) -> String {
+        self.user
+            .as_ref()
+            .map(|x| x.screen_name.clone())
+            .unwrap_or_else(|| "未知用户".to_string())
+    }
+
+    fn plain_text(&self) -> String {
+        html2text::from_read(self.text.as_bytes(), 1000)
+            .trim()
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WeiboComment {
+    pub id: String,
+    pub uname: String,
+    pub text: String,
+    pub created_at: String,
+    pub root: Option<(String, String)>,
+}
+
+impl WeiboComment {
+    fn new(comment: &HotFlowComment, root: Option<&HotFlowComment>) -> Self {
+        WeiboComment {
+            id: comment.id.clone(),
+            uname: comment.user_name(),
+            text: comment.plain_text(),
+            created_at: comment.created_at.clone(),
+            root: root.map(|x| (x.user_name(), x.plain_text())),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct WeiboIndexDataCardMblogPic {
     pub url: String,
+    #[serde(rename = "videoSrc")]
+    pub video_src: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusExtend {
+    data: StatusExtendData,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusExtendData {
+    #[serde(rename = "longTextContent")]
+    long_text_content: Option<String>,
+}
+
+impl WeiboIndexDataCardMblog {
+    pub fn user_name(&self) -> &str {
+        self.user
+            .as_ref()
+            .map(|x| x.screen_name.as_str())
+            .unwrap_or("未知用户")
+    }
+
+    pub fn plain_text(&self) -> String {
+        html2text::from_read(self.text.as_bytes(), 1000)
+            .trim()
+            .to_string()
+    }
+
+    pub fn render(&self) -> String {
+        let mut s = escape_html(&self.plain_text());
+
+        if let Some(origin) = &self.retweeted_status {
+            let origin = if origin.user.is_some() {
+                format!(
+                    "// @{}: {}",
+                    escape_html(origin.user_name()),
+                    origin.render()
+                )
+            } else {
+                "// 原微博不可见".to_string()
+            };
+            if !s.is_empty() {
+                s.push_str("\n\n");
+            }
+            s.push_str(&origin);
+        }
+
+        s
+    }
+
+    pub fn video(&self) -> Option<(String, Option<String>)> {
+        if let Some(origin) = &self.retweeted_status {
+            return origin.video();
+        }
+
+        let page_info = self
+            .page_info
+            .as_ref()
+            .filter(|x| x.page_type.as_deref() == Some("video"))?;
+        let media = page_info.media_info.as_ref()?;
+        let url = [
+            &media.mp4_720p_mp4,
+            &media.mp4_hd_url,
+            &media.mp4_sd_url,
+            &media.stream_url_hd,
+            &media.stream_url,
+        ]
+        .into_iter()
+        .flatten()
+        .find(|x| !x.is_empty())?;
+
+        Some((
+            url.clone(),
+            page_info.page_pic.as_ref().map(|x| x.url.clone()),
+        ))
+    }
+
+    pub fn live_photos(&self) -> Vec<String> {
+        let mut live_photos = self
+            .pics
+            .iter()
+            .flatten()
+            .filter_map(|x| x.video_src.clone())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+
+        if let Some(origin) = &self.retweeted_status {
+            live_photos.extend(origin.live_photos());
+        }
+
+        live_photos
+    }
+
+    pub fn pictures(&self) -> Vec<String> {
+        let mut pictures = self
+            .pics
+            .iter()
+            .flatten()
+            .map(|x| x.url.clone())
+            .collect::<Vec<_>>();
+
+        if let Some(origin) = &self.retweeted_status {
+            pictures.extend(origin.pictures());
+        }
+
+        pictures
+    }
 }
 
 #[derive(Clone)]
 pub struct WeiboClient {
     client: Client,
     cookie_store: Arc<CookieStoreMutex>,
+    admin: Option<(Bot, i64)>,
+    login_method: WeiboLogin,
 }
 
 impl WeiboClient {
-    pub fn new() -> Result<Self> {
+    pub fn new(login_method: WeiboLogin) -> Result<Self> {
         let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(CookieStore::default());
         let cookie_store = Arc::new(cookie_store);
         let cookie_store_clone = cookie_store.clone();
@@ -127,9 +486,19 @@ impl WeiboClient {
                 .timeout(Duration::from_secs(30))
                 .build()?,
             cookie_store: cookie_store_clone,
+            admin: None,
+            login_method,
         })
     }
 
+    pub fn with_admin(mut self, bot: Option<&Bot>, chat_id: Option<i64>) -> Self {
+        if let (Some(bot), Some(chat_id)) = (bot, chat_id) {
+            self.admin = Some((bot.clone(), chat_id));
+        }
+
+        self
+    }
+
     async fn get(
         &self,
         url: &str,
@@ -212,6 +581,185 @@ impl WeiboClient {
         Ok(())
     }
 
+    pub async fn qrcode_login(&self) -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("Referer", QRCODE_REFERER.parse()?);
+
+        let query = &[("entry", "miniblog"), ("size", "180")];
+        let resp = self
+            .get(QRCODE_IMAGE_URL, Some(query), Some(headers.clone()))
+            .await?;
+        let json = resp.json::<QrCodeImage>().await?;
+        let qr = match json.data {
+            Some(data) if json.retcode == 20000000 => data,
+            _ => bail!("Can not get weibo qrcode: {}", json.msg),
+        };
+
+        // the image url is a qrcode generator, the content to scan is in its data param
+        let content = Url::parse(&qr.image)?
+            .query_pairs()
+            .find(|(k, _)| k == "data")
+            .map(|(_, v)| v.to_string())
+            .ok_or_else(|| anyhow!("Can not get weibo qrcode content!"))?;
+
+        let (bot, chat_id) = match &self.admin {
+            Some((bot, chat_id)) => (Some(bot), Some(*chat_id)),
+            None => (None, None),
+        };
+        sender::show_qrcode(&content, "请使用微博客户端扫描二维码登录", bot, chat_id).await?;
+
+        let started_at = Instant::now();
+
+        let login_url = loop {
+            sleep(Duration::from_secs(3)).await;
+
+            let query = &[
+                ("entry", "miniblog"),
+                ("source", "cross_domain"),
+                ("url", "https://weibo.com"),
+                ("qrid", qr.qrid.as_str()),
+            ];
+            let resp = self
+                .get(QRCODE_CHECK_URL, Some(query), Some(headers.clone()))
+                .await?;
+            let json = resp.json::<QrCodeCheck>().await?;
+
+            match json.retcode {
+                20000000 => {
+                    break json
+                        .data
+                        .and_then(|x| x.url)
+                        .ok_or_else(|| anyhow!("Can not get weibo login url!"))?
+                }
+                // 50114001: waiting for scan, 50114002: scanned, waiting for confirm
+                50114001 | 50114002 => {
+                    if started_at.elapsed() > QRCODE_TIMEOUT {
+                        bail!("Weibo qrcode login timeout!");
+                    }
+                }
+                _ => bail!("Failed to login weibo: {}", json.msg),
+            }
+        };
+
+        self.get(&login_url, None, None).await?;
+
+        if !self.is_login().await? {
+            bail!("Weibo qrcode is confirmed but m.weibo.cn is still not login!");
+        }
+        info!("Weibo qrcode login successfully!");
+
+        Ok(())
+    }
+
+    pub async fn visitor_login(&self) -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Referer",
+            "https://passport.weibo.com/visitor/visitor".parse()?,
+        );
+
+        let body = &[("cb", "gen_callback"), ("fp", VISITOR_FP)];
+        let resp = self
+            .post(GEN_VISITOR_URL, Some(headers.clone()), Some(body))
+            .await?;
+        let json: GenVisitor = jsonp(&resp.text().await?)?;
+        let visitor = match json.data {
+            Some(data) if json.retcode == 20000000 => data,
+            _ => bail!("Can not get weibo visitor: {}", json.msg),
+        };
+
+        let w = if visitor.new_tid { "2" } else { "3" };
+        let c = format!("{:03}", visitor.confidence.unwrap_or(100));
+        let query = &[
+            ("a", "incarnate"),
+            ("t", visitor.tid.as_str()),
+            ("w", w),
+            ("c", c.as_str()),
+            ("gc", ""),
+            ("cb", "cross_domain"),
+            ("from", "weibo"),
+        ];
+        let resp = self.get(VISITOR_URL, Some(query), Some(headers)).await?;
+        let json: Incarnate = jsonp(&resp.text().await?)?;
+        let incarnate = match json.data {
+            Some(data) if json.retcode == 20000000 => data,
+            _ => bail!("Can not incarnate weibo visitor: {}", json.msg),
+        };
+
+        // incarnate only sets the cookies on weibo.com, m.weibo.cn needs them too
+        let url = Url::parse("https://m.weibo.cn")?;
+        let mut store = self.cookie_store.lock().map_err(|e| anyhow!("{}", e))?;
+        for (name, value) in [("SUB", &incarnate.sub), ("SUBP", &incarnate.subp)] {
+            store.parse(
+                &format!("{}={}; Domain=.weibo.cn; Path=/", name, value),
+                &url,
+            )?;
+        }
+        info!("Weibo visitor cookies are ready");
+
+        Ok(())
+    }
+
+    pub async fn ensure_login(&self, con: &MultiplexedConnection) -> Result<()> {
+        let (account, password) = match &self.login_method {
+            WeiboLogin::Visitor => return self.visitor_login().await,
+            WeiboLogin::Password { account, password } => (Some(account), Some(password)),
+            WeiboLogin::QrCode => (None, None),
+        };
+
+        if self.load_cookies(con).await? {
+            if self.is_login().await? {
+                info!("Weibo session is restored, skip login");
+                return Ok(());
+            }
+            info!("Weibo session is expired, login again ...");
+        }
+
+        if let (Some(account), Some(password)) = (account, password) {
+            self.login(account, password).await?;
+        } else {
+            self.qrcode_login().await?;
+        }
+        self.save_cookies(con).await?;
+
+        Ok(())
+    }
+
+    pub async fn is_login(&self) -> Result<bool> {
+        let resp = self.get(CONFIG_URL, None, None).await?;
+        let json = resp.json::<WeiboConfig>().await?;
+
+        Ok(json.data.login)
+    }
+
+    pub async fn save_cookies(&self, con: &MultiplexedConnection) -> Result<()> {
+        let mut con = con.clone();
+        let cookies = {
+            let store = self.cookie_store.lock().map_err(|e| anyhow!("{}", e))?;
+            let mut cookies = vec![];
+            for c in store.iter_unexpired() {
+                cookies.push(serde_json::to_string(c)?);
+            }
+            cookies.join("\n")
+        };
+        con.set::<_, _, ()>(COOKIES_KEY, cookies).await?;
+
+        Ok(())
+    }
+
+    pub async fn load_cookies(&self, con: &MultiplexedConnection) -> Result<bool> {
+        let mut con = con.clone();
+        let cookies: Option<String> = con.get(COOKIES_KEY).await?;
+        let cookies = match cookies {
+            Some(cookies) => cookies,
+            None => return Ok(false),
+        };
+        let store = CookieStore::load_json(cookies.as_bytes()).map_err(|e| anyhow!("{}", e))?;
+        *self.cookie_store.lock().map_err(|e| anyhow!("{}", e))? = store;
+
+        Ok(true)
+    }
+
     async fn verification(&self, verif_url: &str) -> Result<String> {
         let resp = self.get(verif_url, None, None).await?;
         let text = resp.text().await?;
@@ -237,15 +785,22 @@ impl WeiboClient {
             }
         }
 
-        let mut reader = Editor::<()>::new();
-        let code = reader.readline(s)?;
-
-        let query = &[("code", code.as_str()), ("msg_type", msg_type)];
-        let resp = self.get(CODE_CHECK_URL, Some(query), None).await?;
-        let json = resp.json::<VeriCheck>().await?;
-        if json.retcode != 100000 {
-            bail!("{}", json.msg)
-        }
+        let mut times = 0;
+        let json = loop {
+            let code = self.read_code(s).await?;
+            let query = &[("code", code.as_str()), ("msg_type", msg_type)];
+            let resp = self.get(CODE_CHECK_URL, Some(query), None).await?;
+            let json = resp.json::<VeriCheck>().await?;
+            if json.retcode == 100000 {
+                break json;
+            }
+            times += 1;
+            if times >= VERIFY_CODE_RETRY {
+                bail!("{}", json.msg)
+            }
+            warn!("Weibo verification code is wrong: {}, retry ...", json.msg);
+            s = "The verification code is wrong, please input again: ";
+        };
         let login_url = json
             .data
             .url
@@ -254,6 +809,23 @@ impl WeiboClient {
         Ok(login_url)
     }
 
+    async fn read_code(&self, prompt: &str) -> Result<String> {
+        if let Some((bot, chat_id)) = &self.admin {
+            info!("Waiting for weibo verification code from telegram admin ...");
+            return sender::ask_admin(bot, *chat_id, prompt, VERIFY_CODE_TIMEOUT).await;
+        }
+
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "Weibo needs verification code but neither admin chat nor terminal is available!"
+            );
+        }
+
+        let mut reader = Editor::<()>::new();
+
+        Ok(reader.readline(prompt)?)
+    }
+
     async fn send_verif(&self, text: &str, msg_type: Option<&str>) -> Result<VerifSMS> {
         let msg_type = msg_type.unwrap_or("sms");
 
@@ -296,40 +868,151 @@ impl WeiboClient {
         Ok(json)
     }
 
-    async fn get_container_id(
-        &self,
-        profile_url: &str,
-        uid: Option<&str>,
-    ) -> Result<(String, String)> {
-        self.get(profile_url, None, None).await?;
-
-        let mut container_id = {
-            let mut match_cookie = None;
-            let regex = Regex::new(r"fid%3D(\d+)%26")?;
-            let store = self.cookie_store.lock().map_err(|e| anyhow!("{}", e))?;
-            for c in store.iter_any() {
-                if let Some(v) = regex.find(c.value())? {
-                    match_cookie = Some(v.as_str());
-                    break;
-                }
-            }
+    pub async fn expand(&self, mblog: &mut WeiboIndexDataCardMblog) -> Result<()> {
+        if let Some(origin) = &mut mblog.retweeted_status {
+            Box::pin(self.expand(origin)).await?;
+        }
 
-            let container_id = match_cookie.ok_or_else(|| anyhow!("Can not get container id!"))?;
+        if !mblog.is_long_text {
+            return Ok(());
+        }
 
-            container_id.replace("fid%3D", "").replace("%26", "")
-        };
+        let resp = self
+            .get(EXTEND_URL, Some(&[("id", mblog.id.as_str())]), None)
+            .await?;
+        let json = resp.json::<StatusExtend>().await?;
+        if let Some(text) = json.data.long_text_content {
+            mblog.text = text;
+            mblog.is_long_text = false;
+        }
+
+        Ok(())
+    }
+
+    async fn get_index(&self, api_url: &str) -> Result<WeiboIndex> {
+        let resp = self.get(api_url, None, None).await?;
+        if resp
+            .url()
+            .host_str()
+            .map(|x| x.starts_with("passport."))
+            .unwrap_or(false)
+        {
+            bail!(WeiboError::SessionExpired);
+        }
+
+        let text = resp.text().await?;
+        // -100 means login is required, nothing with ok 0 may also be an empty timeline
+        if serde_json::from_str::<WeiboOk>(&text)?.ok == -100 {
+            bail!(WeiboError::SessionExpired);
+        }
+        let json = serde_json::from_str::<WeiboIndex>(&text)?;
+
+        Ok(json)
+    }
+
+    pub async fn is_deleted(&self, id: &str) -> Result<bool> {
+        let resp = self.get(SHOW_URL, Some(&[("id", id)]), None).await?;
+        if resp
+            .url()
+            .host_str()
+            .map(|x| x.starts_with("passport."))
+            .unwrap_or(false)
+        {
+            bail!(WeiboError::SessionExpired);
+        }
+        let text = resp.text().await?;
+
+        // a deleted weibo gives ok 0, -100 means login is required
+        match serde_json::from_str::<WeiboOk>(&text)?.ok {
+            -100 => bail!(WeiboError::SessionExpired),
+            ok => Ok(ok == 0),
+        }
+    }
+
+    pub async fn get_watched_comments(&self, uid: &str, mid: &str) -> Result<Vec<WeiboComment>> {
+        let query = &[("id", mid), ("mid", mid), ("max_id_type", "0")];
+        let resp = self.get(HOTFLOW_URL, Some(query), None).await?;
+        let json = resp.json::<HotFlow>().await?;
 
-        let uid = if let Some(uid) = uid {
-            uid.to_string()
+        let mut result = vec![];
+        for root in json.data.map(|x| x.data).unwrap_or_default() {
+            if root.is_from(uid) {
+                result.push(WeiboComment::new(&root, None));
+            }
+            for i in root.comments.iter().filter(|x| x.is_from(uid)) {
+                result.push(WeiboComment::new(i, Some(&root)));
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub async fn get_topic_posts(
+        &self,
+        topic: &str,
+        uid: &str,
+    ) -> Result<Vec<WeiboIndexDataCardMblog>> {
+        let container_id = if topic.contains("_-_") {
+            topic.to_string()
         } else {
-            get_uid(profile_url)?
+            format!("{}_-_feed", topic)
         };
+        let api_url = format!("{}?containerid={}", INDEX_URL, container_id);
+        let json = self.get_index(&api_url).await?;
+
+        let mut cards = vec![];
+        for i in json.data.cards.unwrap_or_default() {
+            match i.card_group {
+                Some(group) => cards.extend(group),
+                None => cards.push(i),
+            }
+        }
 
-        let api_url = format!(API_URL!(), uid, uid, container_id);
+        let posts = cards
+            .into_iter()
+            .filter_map(|x| x.mblog)
+            .filter(|x| {
+                x.user.as_ref().and_then(|x| x.id).map(|x| x.to_string()) == Some(uid.to_string())
+            })
+            .collect();
+
+        Ok(posts)
+    }
+
+    pub async fn resolve_uid(&self, target: &str) -> Result<String> {
+        if let Some(uid) = parse_uid(target) {
+            return Ok(uid);
+        }
+
+        // custom domain like weibo.com/xiaoxiongmao only lives on weibo.com
+        let name =
+            parse_custom_name(target).ok_or_else(|| anyhow!("{} is not a weibo user!", target))?;
+        let mut headers = HeaderMap::new();
+        headers.insert("Referer", format!("https://weibo.com/{}", name).parse()?);
+        let resp = self
+            .get(PROFILE_INFO_URL, Some(&[("custom", &name)]), Some(headers))
+            .await?;
+        if resp
+            .url()
+            .host_str()
+            .map(|x| x.starts_with("passport."))
+            .unwrap_or(false)
+        {
+            bail!(WeiboError::SessionExpired);
+        }
 
-        let resp = self.get(&api_url, None, None).await?;
+        let json = resp.json::<ProfileInfo>().await?;
 
-        let json = resp.json::<WeiboIndex>().await?;
+        json.data
+            .map(|x| x.user.id.to_string())
+            .ok_or_else(|| anyhow!("Can not get uid of {}!", target))
+    }
+
+    async fn get_container_id(&self, uid: &str) -> Result<String> {
+        let mut container_id = format!("{}{}", PROFILE_CONTAINER_PREFIX, uid);
+        let api_url = format!(API_URL!(), uid, uid, container_id);
+
+        let json = self.get_index(&api_url).await?;
 
         let tabs = json
             .data
@@ -343,53 +1026,216 @@ impl WeiboClient {
             }
         }
 
-        Ok((container_id, uid))
+        Ok(container_id)
     }
 
     pub async fn get_ailurus(
         &self,
-        profile_url: &str,
+        uid: &str,
         container_id: Option<String>,
     ) -> Result<(WeiboIndex, String)> {
-        let (container_id, uid) = if container_id.is_none() {
-            self.get_container_id(profile_url, None).await?
+        let container_id = if let Some(container_id) = container_id {
+            container_id
         } else {
-            (container_id.unwrap(), get_uid(profile_url)?)
+            self.get_container_id(uid).await?
         };
 
         let api_url = format!(API_URL!(), uid, uid, container_id);
-        let resp = self.get(&api_url, None, None).await;
+        match self.get_index(&api_url).await {
+            Ok(json) if !json.is_empty() => return Ok((json, container_id)),
+            Err(e) if e.is::<WeiboError>() => return Err(e),
+            Ok(_) => warn!("Weibo index of {} is empty, get container id again", uid),
+            Err(e) => warn!(
+                "Can not get weibo index of {}, get container id again: {}",
+                uid, e
+            ),
+        }
 
-        let (json, container_id) = if let Ok(resp) = resp {
-            let json = resp.json::<WeiboIndex>().await?;
+        // the container id may be stale
+        let container_id = self.get_container_id(uid).await?;
+        let api_url = format!(API_URL!(), uid, uid, container_id);
+        let json = self.get_index(&api_url).await?;
+        // still nothing, only an expired session when the client is not logged in any more
+        if json.is_empty()
+            && !matches!(self.login_method, WeiboLogin::Visitor)
+            && !self.is_login().await?
+        {
+            bail!(WeiboError::SessionExpired);
+        }
 
-            (json, container_id)
-        } else {
-            let (container_id, uid) = self.get_container_id(profile_url, None).await?;
-            let api_url = format!(API_URL!(), uid, uid, container_id);
-            let resp = self.get(&api_url, None, None).await?;
-            let json = resp.json::<WeiboIndex>().await?;
+        Ok((json, container_id))
+    }
+}
 
-            (json, container_id)
-        };
+fn jsonp<T: DeserializeOwned>(text: &str) -> Result<T> {
+    let start = text
+        .find('(')
+        .ok_or_else(|| anyhow!("Can not find jsonp start!"))?;
+    let end = text
+        .rfind(')')
+        .ok_or_else(|| anyhow!("Can not find jsonp end!"))?;
 
-        Ok((json, container_id))
+    Ok(serde_json::from_str(&text[start + 1..end])?)
+}
+
+pub fn parse_uid(target: &str) -> Option<String> {
+    let target = target.trim();
+    if !target.is_empty() && target.chars().all(|x| x.is_ascii_digit()) {
+        return Some(target.to_string());
+    }
+
+    let url = Url::parse(target).ok()?;
+    if let Some((_, uid)) = url.query_pairs().find(|(k, _)| k == "uid") {
+        return Some(uid.to_string());
+    }
+
+    // weibo.com/u/123, m.weibo.cn/u/123, m.weibo.cn/profile/123 and weibo.com/123
+    let mut segments = url.path_segments()?.filter(|x| !x.is_empty());
+    let uid = match segments.next()? {
+        "u" | "profile" => segments.next()?,
+        x => x,
+    };
+
+    if uid.chars().all(|x| x.is_ascii_digit()) {
+        Some(uid.to_string())
+    } else {
+        None
     }
 }
 
-pub fn get_uid(profile_url: &str) -> Result<String> {
-    let url = Url::parse(profile_url)?;
-    let query = url
-        .query()
-        .ok_or_else(|| anyhow!("Can not get url query!"))?;
-    let query_vec = query.split('&');
-    let mut uid = None;
-    for i in query_vec {
-        if i.starts_with("uid") {
-            uid = i.split('=').nth(1);
-            break;
-        }
+// weibo.com/xiaoxiongmao, m.weibo.cn/xiaoxiongmao or just xiaoxiongmao
+fn parse_custom_name(target: &str) -> Option<String> {
+    let target = target.trim();
+    let name = match Url::parse(target) {
+        Ok(url) => url.path_segments()?.find(|x| !x.is_empty())?.to_string(),
+        Err(_) => target.trim_matches('/').to_string(),
+    };
+
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-')
+    {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_jsonp() {
+    let json: GenVisitor = jsonp(
+        r#"window.gen_callback && gen_callback({"retcode":20000000,"msg":"succ","data":{"tid":"abc=","new_tid":true,"confidence":95}});"#,
+    )
+    .unwrap();
+    let data = json.data.unwrap();
+    assert_eq!(data.tid, "abc=");
+    assert!(data.new_tid);
+    assert_eq!(data.confidence, Some(95));
+}
+
+#[test]
+fn test_render_retweet() {
+    let mblog: WeiboIndexDataCardMblog = serde_json::from_str(
+        r#"{
+            "id": "2",
+            "user": {"screen_name": "小熊猫"},
+            "created_at": "刚刚",
+            "text": "转发 &lt;3<br />嗯",
+            "retweeted_status": {
+                "id": "1",
+                "user": {"screen_name": "原作者"},
+                "created_at": "1分钟前",
+                "pics": [{"url": "https://wx1.sinaimg.cn/orj360/1.jpg"}],
+                "text": "原文"
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(mblog.render(), "转发 &lt;3\n嗯\n\n// @原作者: 原文");
+    assert_eq!(
+        mblog.pictures(),
+        vec!["https://wx1.sinaimg.cn/orj360/1.jpg"]
+    );
+    assert!(mblog.live_photos().is_empty());
+    assert!(mblog.video().is_none());
+}
+
+#[test]
+fn test_video_and_live_photos() {
+    let mblog: WeiboIndexDataCardMblog = serde_json::from_str(
+        r#"{
+            "id": "1",
+            "user": {"screen_name": "小熊猫"},
+            "created_at": "刚刚",
+            "text": "视频",
+            "pics": [{"url": "https://wx1.sinaimg.cn/orj360/1.jpg", "videoSrc": "https://video.weibo.com/media/play?livephoto=1.mov"}],
+            "page_info": {
+                "type": "video",
+                "page_pic": {"url": "https://wx1.sinaimg.cn/orj480/cover.jpg"},
+                "media_info": {"mp4_720p_mp4": "", "mp4_hd_url": "https://f.video.weibocdn.com/hd.mp4", "stream_url": "https://f.video.weibocdn.com/sd.mp4"}
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        mblog.video(),
+        Some((
+            "https://f.video.weibocdn.com/hd.mp4".to_string(),
+            Some("https://wx1.sinaimg.cn/orj480/cover.jpg".to_string())
+        ))
+    );
+    assert_eq!(
+        mblog.live_photos(),
+        vec!["https://video.weibo.com/media/play?livephoto=1.mov"]
+    );
+}
+
+#[test]
+fn test_parse_uid() {
+    for i in [
+        "1234567890",
+        "https://weibo.com/u/1234567890",
+        "https://m.weibo.cn/u/1234567890?uid=1234567890&luicode=10000011",
+        "https://m.weibo.cn/profile/1234567890",
+        "https://weibo.com/1234567890?refer_flag=1005055013_",
+        "https://m.weibo.cn/p/index?containerid=1005051234567890&uid=1234567890",
+    ] {
+        assert_eq!(parse_uid(i).as_deref(), Some("1234567890"), "{}", i);
     }
+    assert_eq!(parse_uid("https://weibo.com/xiaoxiongmao"), None);
+    for i in [
+        "xiaoxiongmao",
+        "https://weibo.com/xiaoxiongmao",
+        "https://m.weibo.cn/xiaoxiongmao?from=search",
+    ] {
+        assert_eq!(
+            parse_custom_name(i).as_deref(),
+            Some("xiaoxiongmao"),
+            "{}",
+            i
+        );
+    }
+    assert_eq!(parse_custom_name("小熊猫"), None);
+}
 
-    Ok(uid.ok_or_else(|| anyhow!("Can not get uid!"))?.to_string())
+#[test]
+fn test_hotflow_comments() {
+    let json: HotFlow = serde_json::from_str(
+        r#"{"ok":1,"data":{"data":[
+            {"id":"1","created_at":"刚刚","text":"好耶","user":{"id":2,"screen_name":"粉丝"},"comments":[
+                {"id":"3","created_at":"刚刚","text":"回复<a href='/n/粉丝'>@粉丝</a>:谢谢","user":{"id":1,"screen_name":"小熊猫"},"comments":false}
+            ]},
+            {"id":"4","created_at":"刚刚","text":"置顶","user":{"id":1,"screen_name":"小熊猫"},"comments":false}
+        ]}}"#,
+    )
+    .unwrap();
+    let data = json.data.unwrap().data;
+
+    assert!(!data[0].is_from("1"));
+    assert!(data[1].is_from("1"));
+    assert!(data[0].comments[0].is_from("1"));
+    assert!(data[1].comments.is_empty());
 }